@@ -1,4 +1,6 @@
+use crate::diagnostics::quote;
 use crate::output::BufferedStdout;
+use crate::theme::{Preset, Theme};
 use alloc::vec::Vec;
 use veneer::CStr;
 
@@ -16,33 +18,70 @@ pub struct App {
     pub recurse: bool,
     pub sort_field: Option<SortField>,
     pub time_field: TimeField,
+    pub time_style: TimeStyle,
     pub list_directory_contents: bool,
     pub out: BufferedStdout,
     pub convert_id_to_name: bool,
     pub print_owner: bool,
     pub print_group: bool,
     pub color: Color,
+    pub theme: crate::theme::Theme,
+    pub git_ignore: GitIgnore,
+    pub ignore: Option<crate::ignore::Ignore>,
+    pub dired: Option<crate::output::Dired>,
+    pub show_git_status: bool,
+    pub git: Option<crate::git::Repo>,
+    pub walker: Option<crate::walk::Walker>,
+    /// Device and inode of each directory being listed, outermost first, when following symlinks
+    pub ancestors: Vec<(libc::dev_t, libc::ino64_t)>,
+    /// The worst problem so far, which decides the exit status
+    pub problem: Option<crate::error::Error>,
+    /// How many directories deep recursion is, so problems with arguments count for more
+    pub depth: usize,
+    pub one_file_system: bool,
+    /// Device of the argument currently being listed, for --one-file-system
+    pub root_device: libc::dev_t,
+    /// statfs magic numbers of the filesystems recursion does not enter
+    pub skip_filesystems: Vec<u32>,
+    pub ring: Option<crate::uring::Ring>,
 
     pub args: Vec<CStr<'static>>,
-    pub uid_names: Vec<(u32, Vec<u8>)>,
-    pub gid_names: Vec<(u32, Vec<u8>)>,
+    pub ids: crate::ids::Resolver,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Color {
-    Always,
-    Auto,
+    /// Looks up mode bits and link targets where the directory entry type cannot tell
+    Full,
+    /// Goes by the directory entry type alone, which costs no system calls
+    Fast,
     Never,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GitIgnore {
+    Off,
+    Hide,
+    Dim,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum TimeField {
     Modified,
     StatusModified,
-    Created,
     Accessed,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TimeStyle {
+    /// Month, day and either time or year, as ls shows them
+    Locale,
+    /// "5 minutes ago"
+    Relative,
+    /// "5m"
+    Compact,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum SortField {
     Name,
@@ -53,6 +92,8 @@ pub enum SortField {
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum FollowSymlinks {
     Never,
+    /// Symlinks to directories named on the command line are listed as those directories
+    DirectoriesWhenExplicit,
     WhenExplicit,
     Always,
 }
@@ -61,6 +102,7 @@ pub enum FollowSymlinks {
 pub enum Suffixes {
     None,
     Directories,
+    FileType,
     All,
 }
 
@@ -71,6 +113,16 @@ pub enum ShowAll {
     Almost,
 }
 
+impl ShowAll {
+    pub fn shows(self, name: &[u8]) -> bool {
+        match self {
+            ShowAll::No => name.first() != Some(&b'.'),
+            ShowAll::Almost => name != b".." && name != b".",
+            ShowAll::Yes => true,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum SortDirection {
     Horizontal,
@@ -85,40 +137,284 @@ pub enum DisplayMode {
     Stream,
 }
 
+/// What an option does, for the code that applies it
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Id {
+    All,
+    AlmostAll,
+    Directory,
+    GitIgnore,
+    SortSize,
+    SortTime,
+    SortChangeTime,
+    SortAccessTime,
+    Unsorted,
+    UnsortedAll,
+    Reverse,
+    Sort,
+    Time,
+    Columns,
+    Across,
+    Commas,
+    OnePerLine,
+    Long,
+    Format,
+    Width,
+    Color,
+    Classify,
+    FileType,
+    Slash,
+    IndicatorStyle,
+    HideControlChars,
+    NoOwner,
+    NoGroup,
+    NumericIds,
+    Inode,
+    Size,
+    Kibibytes,
+    Dired,
+    Git,
+    TimeStyle,
+    Theme,
+    ColorScale,
+    DereferenceCommandLine,
+    Dereference,
+    Recursive,
+    OneFileSystem,
+    SkipFs,
+    Parallel,
+    IoUring,
+    FastColor,
+    Help,
+    Version,
+    Completions,
+    NoConfig,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Takes {
+    Nothing,
+    Value,
+    /// Only in the `--name=value` form, so a following argument is never mistaken for it
+    OptionalValue,
+}
+
+/// Sections of --help
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Filtering,
+    Sorting,
+    Layout,
+    Details,
+    Traversal,
+    Performance,
+    Information,
+}
+
+impl Category {
+    pub const ALL: [Category; 7] = [
+        Category::Filtering,
+        Category::Sorting,
+        Category::Layout,
+        Category::Details,
+        Category::Traversal,
+        Category::Performance,
+        Category::Information,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Category::Filtering => "Choosing entries",
+            Category::Sorting => "Sorting",
+            Category::Layout => "Layout",
+            Category::Details => "Long listings",
+            Category::Traversal => "Symlinks and recursion",
+            Category::Performance => "Performance",
+            Category::Information => "Information",
+        }
+    }
+}
+
+pub struct OptionSpec {
+    pub id: Id,
+    pub short: Option<u8>,
+    pub long: Option<&'static str>,
+    pub takes: Takes,
+    /// What --help calls the value
+    pub value_name: &'static str,
+    /// Every accepted value, for options that take one from a fixed set
+    pub choices: &'static [&'static str],
+    pub category: Category,
+    pub help: &'static str,
+}
+
+impl OptionSpec {
+    const fn new(
+        id: Id,
+        short: Option<u8>,
+        long: Option<&'static str>,
+        category: Category,
+        help: &'static str,
+    ) -> Self {
+        OptionSpec {
+            id,
+            short,
+            long,
+            takes: Takes::Nothing,
+            value_name: "",
+            choices: &[],
+            category,
+            help,
+        }
+    }
+
+    const fn value(self, value_name: &'static str, choices: &'static [&'static str]) -> Self {
+        OptionSpec {
+            takes: Takes::Value,
+            value_name,
+            choices,
+            ..self
+        }
+    }
+
+    const fn optional_value(
+        self,
+        value_name: &'static str,
+        choices: &'static [&'static str],
+    ) -> Self {
+        OptionSpec {
+            takes: Takes::OptionalValue,
+            value_name,
+            choices,
+            ..self
+        }
+    }
+}
+
+const WHEN: &[&str] = &["always", "auto", "never"];
+
+/// Every option fls understands. Parsing, --help and suggestions for typos all come from here.
+#[rustfmt::skip]
+pub static OPTIONS: &[OptionSpec] = {
+    use Category::*;
+    &[
+        OptionSpec::new(Id::All, Some(b'a'), Some("all"), Filtering, "do not ignore entries starting with ."),
+        OptionSpec::new(Id::AlmostAll, Some(b'A'), Some("almost-all"), Filtering, "do not list implied . and .."),
+        OptionSpec::new(Id::Directory, Some(b'd'), Some("directory"), Filtering, "list directories themselves, not their contents"),
+        OptionSpec::new(Id::GitIgnore, None, Some("gitignore"), Filtering, "hide or dim entries that git ignores")
+            .optional_value("MODE", &["hide", "dim"]),
+
+        OptionSpec::new(Id::SortSize, Some(b'S'), None, Sorting, "sort by file size, largest first"),
+        OptionSpec::new(Id::SortTime, Some(b't'), None, Sorting, "sort by modification time, newest first"),
+        OptionSpec::new(Id::SortChangeTime, Some(b'c'), None, Sorting, "sort by, and show, status change time"),
+        OptionSpec::new(Id::SortAccessTime, Some(b'u'), None, Sorting, "sort by, and show, access time"),
+        OptionSpec::new(Id::Unsorted, Some(b'U'), None, Sorting, "do not sort; list entries in directory order"),
+        OptionSpec::new(Id::UnsortedAll, Some(b'f'), None, Sorting, "like -aU"),
+        OptionSpec::new(Id::Reverse, Some(b'r'), Some("reverse"), Sorting, "reverse order while sorting"),
+        OptionSpec::new(Id::Sort, None, Some("sort"), Sorting, "sort by WORD instead of name")
+            .value("WORD", &["none", "name", "size", "time"]),
+        OptionSpec::new(Id::Time, None, Some("time"), Sorting, "show this time instead of the modification time")
+            .value("WORD", &["atime", "access", "use", "ctime", "status", "mtime", "modification"]),
+
+        OptionSpec::new(Id::Columns, Some(b'C'), None, Layout, "list entries by columns"),
+        OptionSpec::new(Id::Across, Some(b'x'), None, Layout, "list entries by lines instead of by columns"),
+        OptionSpec::new(Id::Commas, Some(b'm'), None, Layout, "fill width with a comma separated list of entries"),
+        OptionSpec::new(Id::OnePerLine, Some(b'1'), None, Layout, "list one entry per line"),
+        OptionSpec::new(Id::Long, Some(b'l'), None, Layout, "use a long listing format"),
+        OptionSpec::new(Id::Format, None, Some("format"), Layout, "choose the layout by name instead of with -C, -x, -m, -l or -1")
+            .value("WORD", &["across", "commas", "horizontal", "long", "verbose", "single-column", "vertical"]),
+        OptionSpec::new(Id::Width, Some(b'w'), Some("width"), Layout, "set output width to COLS; 0 means no limit")
+            .value("COLS", &[]),
+        OptionSpec::new(Id::Color, None, Some("color"), Layout, "colorize the output always, auto (only on a terminal) or never")
+            .optional_value("WHEN", WHEN),
+        OptionSpec::new(Id::Classify, Some(b'F'), Some("classify"), Layout, "append indicator (one of */=>@|) to entries")
+            .optional_value("WHEN", WHEN),
+        OptionSpec::new(Id::FileType, None, Some("file-type"), Layout, "likewise, except do not append '*'"),
+        OptionSpec::new(Id::Slash, Some(b'p'), None, Layout, "append / indicator to directories"),
+        OptionSpec::new(Id::IndicatorStyle, None, Some("indicator-style"), Layout, "append indicator with style WORD to entry names")
+            .value("WORD", &["none", "slash", "file-type", "classify"]),
+        OptionSpec::new(Id::HideControlChars, Some(b'q'), Some("hide-control-chars"), Layout, "print ? instead of nongraphic characters"),
+
+        OptionSpec::new(Id::NoOwner, Some(b'g'), None, Details, "like -l, but do not list owner"),
+        OptionSpec::new(Id::NoGroup, Some(b'o'), None, Details, "like -l, but do not list group"),
+        OptionSpec::new(Id::NumericIds, Some(b'n'), Some("numeric-uid-gid"), Details, "like -l, but list numeric user and group IDs"),
+        OptionSpec::new(Id::Inode, Some(b'i'), Some("inode"), Details, "print the index number of each entry"),
+        OptionSpec::new(Id::Size, Some(b's'), Some("size"), Details, "print the allocated size of each entry, in blocks"),
+        OptionSpec::new(Id::Kibibytes, Some(b'k'), Some("kibibytes"), Details, "count blocks of 1024 bytes"),
+        OptionSpec::new(Id::Dired, Some(b'D'), Some("dired"), Details, "generate output designed for Emacs' dired mode"),
+        #[cfg(feature = "git")]
+        OptionSpec::new(Id::Git, None, Some("git"), Details, "show the git status of each entry"),
+        OptionSpec::new(Id::TimeStyle, None, Some("time-style"), Details, "show times by date, or relative to now in words or in short")
+            .value("STYLE", &["locale", "relative", "compact"]),
+        OptionSpec::new(Id::Theme, None, Some("theme"), Details, "color the columns for a dark or light background")
+            .value("WORD", &["dark", "light"]),
+        OptionSpec::new(Id::ColorScale, None, Some("color-scale"), Details, "color sizes by magnitude and times by age, or only WHAT")
+            .optional_value("WHAT", &["all", "age", "size"]),
+
+        OptionSpec::new(Id::DereferenceCommandLine, Some(b'H'), Some("dereference-command-line"), Traversal, "follow symbolic links listed on the command line"),
+        OptionSpec::new(Id::Dereference, Some(b'L'), Some("dereference"), Traversal, "show what symbolic links point to, not the links"),
+        OptionSpec::new(Id::Recursive, Some(b'R'), Some("recursive"), Traversal, "list subdirectories recursively"),
+        OptionSpec::new(Id::OneFileSystem, None, Some("one-file-system"), Traversal, "do not recurse into other filesystems"),
+        OptionSpec::new(Id::SkipFs, None, Some("skip-fs"), Traversal, "do not recurse into filesystems of the comma separated TYPES")
+            .value("TYPES", &[]),
+
+        #[cfg(feature = "parallel")]
+        OptionSpec::new(Id::Parallel, None, Some("parallel"), Performance, "read ahead on N threads when recursing, one per CPU by default")
+            .optional_value("N", &[]),
+        #[cfg(feature = "io-uring")]
+        OptionSpec::new(Id::IoUring, None, Some("io-uring"), Performance, "stat entries in batches through io_uring"),
+        OptionSpec::new(Id::FastColor, None, Some("fast-color"), Performance, "color by directory entry type alone, skipping extra lookups"),
+
+        OptionSpec::new(Id::Help, None, Some("help"), Information, "display this help and exit"),
+        OptionSpec::new(Id::Version, None, Some("version"), Information, "output version information and exit"),
+        OptionSpec::new(Id::Completions, None, Some("completions"), Information, "output a completion script for SHELL and exit")
+            .value("SHELL", &["bash", "zsh", "fish"]),
+        OptionSpec::new(Id::NoConfig, None, Some("no-config"), Information, "ignore the config and theme files and FLS_OPTIONS"),
+    ]
+};
+
 impl App {
-    pub fn from_arguments(raw_args: Vec<CStr<'static>>) -> Result<Self, crate::Error> {
+    pub fn from_arguments(raw_args: Vec<CStr<'static>>) -> Result<Self, crate::error::Error> {
         let mut args = Vec::with_capacity(raw_args.len());
-        let mut switches = Vec::with_capacity(16);
-        let mut named_arguments = Vec::new();
+        let mut given = Vec::with_capacity(16);
         let mut args_valid = true;
 
-        let mut hit_only_arg_marker = false;
-
-        for arg in raw_args.into_iter().skip(1) {
-            if arg.as_bytes() == b"--" {
-                hit_only_arg_marker = true;
-            } else if hit_only_arg_marker {
-                args.push(arg);
-            // Things like --color=always
-            } else if arg.as_bytes().starts_with(b"--") {
-                named_arguments.push(arg);
-            // Things like -R
-            } else if arg.get(0) == Some(b'-') {
-                switches.extend(arg.as_bytes().iter().cloned().skip(1));
-            } else {
-                args.push(arg);
-            }
+        // Defaults come first so that the command line overrides them, but whether to read them at
+        // all is up to the command line
+        let no_config = raw_args
+            .iter()
+            .skip(1)
+            .take_while(|arg| arg.as_bytes() != b"--")
+            .any(|arg| arg.as_bytes() == b"--no-config");
+        if !no_config {
+            args_valid &= parse_defaults(&mut given);
         }
+        args_valid &= parse_words(raw_args.into_iter().skip(1), b"", &mut given, &mut args);
         if args.is_empty() {
             args.push(CStr::from_bytes(b".\0"));
         }
 
+        if args_valid {
+            if given.iter().any(|(id, _)| *id == Id::Help) {
+                write_help();
+                unsafe { libc::exit(0) };
+            }
+            if given.iter().any(|(id, _)| *id == Id::Version) {
+                write_version();
+                unsafe { libc::exit(0) };
+            }
+            if let Some((_, Some(shell))) = given.iter().find(|(id, _)| *id == Id::Completions) {
+                crate::completions::write(shell);
+                unsafe { libc::exit(0) };
+            }
+        }
+
         let mut app = App {
             print_inode: false,
             block_size_is_kilobytes: false,
             replace_unprintable_bytes: false,
             reverse_sorting: false,
-            grid_sort_direction: SortDirection::Horizontal,
+            grid_sort_direction: SortDirection::Vertical,
             display_size_in_blocks: false,
             display_mode: DisplayMode::Grid(0),
             show_all: ShowAll::No,
@@ -127,163 +423,273 @@ impl App {
             recurse: false,
             sort_field: Some(SortField::Name),
             time_field: TimeField::Modified,
+            time_style: TimeStyle::Locale,
             list_directory_contents: true,
             convert_id_to_name: true,
             print_owner: true,
             print_group: true,
-            color: Color::Always,
+            color: Color::Never,
+            theme: Theme::preset(Preset::Dark),
+            git_ignore: GitIgnore::Off,
+            ignore: None,
+            dired: None,
+            show_git_status: false,
+            git: None,
+            walker: None,
+            ancestors: Vec::new(),
+            problem: None,
+            depth: 0,
+            one_file_system: false,
+            root_device: 0,
+            skip_filesystems: Vec::new(),
+            ring: None,
             out: BufferedStdout::terminal(),
             args,
-            uid_names: Vec::new(),
-            gid_names: Vec::new(),
+            ids: crate::ids::Resolver::new(),
         };
 
-        for arg in named_arguments.iter().map(CStr::as_bytes) {
-            if let Some(p) = arg.iter().position(|b| *b == b'=') {
-                let (name, value) = arg.split_at(p);
-                let name = &name[2..]; // Trim off the --
-                let value = &value[1..]; // Trim off the =
-                if name == b"color" {
-                    match value {
-                        b"always" => app.color = Color::Always,
-                        b"auto" => app.color = Color::Auto,
-                        b"never" => app.color = Color::Never,
-                        _ => {
-                            error!(b"invalid argument \'", value, b"\' for \'", name, b"'\n");
-                        }
-                    }
-                } else {
-                    error!(b"unrecognized option \'", arg, b"\'\n");
-                }
-            } else {
-                error!(b"unrecognized option \'", arg, b"\'\n");
-            }
-        }
-
-        for switch in switches.iter().cloned() {
-            match switch {
-                b'A' => {
-                    app.show_all = ShowAll::Almost;
-                }
-
-                b'C' => {
-                    app.display_mode = DisplayMode::Grid(0);
-                    app.grid_sort_direction = SortDirection::Horizontal;
-                }
-                b'F' => {
-                    if !switches.contains(&b'H') && !switches.contains(&b'L') {
-                        app.follow_symlinks = FollowSymlinks::Never;
-                    }
-                    app.suffixes = Suffixes::All;
-                }
-                b'H' => {
-                    app.follow_symlinks = FollowSymlinks::WhenExplicit;
-                }
-                b'L' => {
-                    app.follow_symlinks = FollowSymlinks::Always;
-                }
-                b'R' => {
-                    app.recurse = true;
-                }
-                b'S' => {
-                    app.sort_field = Some(SortField::Size);
-                }
-                b'a' => {
-                    app.show_all = ShowAll::Yes;
+        let stdout_size = veneer::syscalls::winsize().ok();
+        let mut parallel = None;
+        let mut explicit_grid = false;
+        let mut width_argument = None;
+        let mut color_when = None;
+        let mut preset = Preset::Dark;
+        let mut scale = None;
+        for (id, value) in given.iter().cloned() {
+            match id {
+                Id::All => app.show_all = ShowAll::Yes,
+                Id::AlmostAll => app.show_all = ShowAll::Almost,
+                Id::Directory => app.list_directory_contents = false,
+                Id::GitIgnore => match value {
+                    Some(b"dim") => app.git_ignore = GitIgnore::Dim,
+                    _ => app.git_ignore = GitIgnore::Hide,
+                },
+                Id::SortSize => app.sort_field = Some(SortField::Size),
+                Id::SortTime => {
+                    app.time_field = TimeField::Modified;
+                    app.sort_field = Some(SortField::Time);
                 }
-                b'c' => {
+                Id::SortChangeTime => {
                     app.time_field = TimeField::StatusModified;
                     app.sort_field = Some(SortField::Time);
                 }
-                b'd' => {
-                    if !switches.contains(&b'H') && !switches.contains(&b'L') {
-                        app.follow_symlinks = FollowSymlinks::Never;
-                    }
-                    app.list_directory_contents = false;
+                Id::SortAccessTime => {
+                    app.time_field = TimeField::Accessed;
+                    app.sort_field = Some(SortField::Time);
                 }
-                b'f' => {
+                Id::Unsorted => app.sort_field = None,
+                Id::UnsortedAll => {
                     app.sort_field = None;
                     app.show_all = ShowAll::Yes;
                 }
-                b'g' => {
-                    app.display_mode = DisplayMode::Long;
-                    app.print_owner = false;
-                }
-                b'i' => {
-                    app.print_inode = true;
-                }
-                b'k' => {
-                    app.block_size_is_kilobytes = true;
-                }
-                b'l' => {
-                    app.display_mode = DisplayMode::Long;
+                Id::Reverse => app.reverse_sorting = true,
+                Id::Sort => match value {
+                    Some(b"none") => app.sort_field = None,
+                    Some(b"size") => app.sort_field = Some(SortField::Size),
+                    Some(b"time") => app.sort_field = Some(SortField::Time),
+                    _ => app.sort_field = Some(SortField::Name),
+                },
+                Id::Time => match value {
+                    Some(b"atime") | Some(b"access") | Some(b"use") => {
+                        app.time_field = TimeField::Accessed
+                    }
+                    Some(b"ctime") | Some(b"status") => app.time_field = TimeField::StatusModified,
+                    _ => app.time_field = TimeField::Modified,
+                },
+                Id::Columns => {
+                    app.display_mode = DisplayMode::Grid(0);
+                    app.grid_sort_direction = SortDirection::Vertical;
+                    explicit_grid = true;
                 }
-                b'm' => {
-                    app.display_mode = DisplayMode::Stream;
+                Id::Across => {
+                    app.display_mode = DisplayMode::Grid(0);
+                    app.grid_sort_direction = SortDirection::Horizontal;
+                    explicit_grid = true;
                 }
-                b'n' => {
+                Id::Commas => app.display_mode = DisplayMode::Stream,
+                Id::OnePerLine => match app.display_mode {
+                    DisplayMode::Long => {}
+                    _ => app.display_mode = DisplayMode::SingleColumn,
+                },
+                Id::Long => app.display_mode = DisplayMode::Long,
+                Id::Format => match value {
+                    Some(b"long") | Some(b"verbose") => app.display_mode = DisplayMode::Long,
+                    Some(b"commas") => app.display_mode = DisplayMode::Stream,
+                    Some(b"single-column") => app.display_mode = DisplayMode::SingleColumn,
+                    Some(b"across") | Some(b"horizontal") => {
+                        app.display_mode = DisplayMode::Grid(0);
+                        app.grid_sort_direction = SortDirection::Horizontal;
+                        explicit_grid = true;
+                    }
+                    _ => {
+                        app.display_mode = DisplayMode::Grid(0);
+                        app.grid_sort_direction = SortDirection::Vertical;
+                        explicit_grid = true;
+                    }
+                },
+                Id::Width => width_argument = value,
+                Id::Color => color_when = Some(value.unwrap_or(b"always")),
+                Id::Theme => match value {
+                    Some(b"light") => preset = Preset::Light,
+                    _ => preset = Preset::Dark,
+                },
+                Id::TimeStyle => match value {
+                    Some(b"relative") => app.time_style = TimeStyle::Relative,
+                    Some(b"compact") => app.time_style = TimeStyle::Compact,
+                    _ => app.time_style = TimeStyle::Locale,
+                },
+                Id::ColorScale => scale = Some(value.unwrap_or(b"all")),
+                Id::Classify => match value {
+                    Some(b"never") => app.suffixes = Suffixes::None,
+                    // Away from a terminal, auto leaves any earlier indicator style in place
+                    Some(b"auto") if stdout_size.is_none() => {}
+                    _ => app.suffixes = Suffixes::All,
+                },
+                Id::FileType => app.suffixes = Suffixes::FileType,
+                Id::Slash => app.suffixes = Suffixes::Directories,
+                Id::IndicatorStyle => match value {
+                    Some(b"slash") => app.suffixes = Suffixes::Directories,
+                    Some(b"file-type") => app.suffixes = Suffixes::FileType,
+                    Some(b"classify") => app.suffixes = Suffixes::All,
+                    _ => app.suffixes = Suffixes::None,
+                },
+                Id::HideControlChars => app.replace_unprintable_bytes = true,
+                Id::NoOwner => {
                     app.display_mode = DisplayMode::Long;
-                    app.convert_id_to_name = false;
+                    app.print_owner = false;
                 }
-                b'o' => {
+                Id::NoGroup => {
                     app.display_mode = DisplayMode::Long;
                     app.print_group = false;
                 }
-                b'p' => {
-                    app.suffixes = Suffixes::Directories;
-                }
-                b'q' => {
-                    app.replace_unprintable_bytes = true;
-                }
-                b'r' => {
-                    app.reverse_sorting = true;
-                }
-                b's' => {
-                    app.display_size_in_blocks = true;
-                }
-                b't' => {
-                    app.time_field = TimeField::Modified;
-                    app.sort_field = Some(SortField::Time);
-                }
-                b'u' => {
-                    app.time_field = TimeField::Accessed;
-                    app.sort_field = Some(SortField::Time);
+                Id::NumericIds => {
+                    app.display_mode = DisplayMode::Long;
+                    app.convert_id_to_name = false;
                 }
-                b'x' => {
-                    app.grid_sort_direction = SortDirection::Horizontal;
+                Id::Inode => app.print_inode = true,
+                Id::Size => app.display_size_in_blocks = true,
+                Id::Kibibytes => app.block_size_is_kilobytes = true,
+                Id::Dired => app.dired = Some(Default::default()),
+                Id::Git => app.show_git_status = true,
+                Id::DereferenceCommandLine => app.follow_symlinks = FollowSymlinks::WhenExplicit,
+                Id::Dereference => app.follow_symlinks = FollowSymlinks::Always,
+                Id::Recursive => app.recurse = true,
+                Id::OneFileSystem => app.one_file_system = true,
+                Id::SkipFs => {
+                    let filesystems = value.unwrap_or_default().split(|b| *b == b',');
+                    app.skip_filesystems
+                        .extend(filesystems.filter_map(crate::fs::filesystem_magic));
                 }
-                b'1' => match app.display_mode {
-                    DisplayMode::Long => {}
-                    _ => app.display_mode = DisplayMode::SingleColumn,
-                },
-                s => {
-                    error!(b"invalid option \'", &[s], b"\'\n");
-                    args_valid = false;
+                Id::Parallel => parallel = Some(value.and_then(parse_number).unwrap_or(0)),
+                // Falls back to one stat at a time if io_uring is unavailable
+                Id::IoUring => app.ring = crate::uring::Ring::new(),
+                Id::Help | Id::Version | Id::Completions | Id::NoConfig | Id::FastColor => {}
+            }
+        }
+
+        let stdout_width = stdout_size.map(|d| d.ws_col as usize).filter(|w| *w > 0);
+
+        // An explicit width wins, then $COLUMNS, then whichever of stdout and stderr is a terminal
+        let mut width = None;
+        if let Some(value) = width_argument {
+            width = parse_number(value);
+        }
+        if width.is_none() {
+            if let Some(value) = crate::fs::getenv(b"COLUMNS\0") {
+                width = parse_number(value);
+                if width.is_none() && !value.is_empty() {
+                    error!(
+                        b"ignoring invalid width in environment variable COLUMNS: ",
+                        quote(value)
+                    );
                 }
             }
         }
+        let width = width.or(stdout_width).or_else(stderr_width).unwrap_or(80);
+        // A width of 0 means there is no limit
+        let width = if width == 0 { usize::MAX } else { width };
 
-        let terminal_width = veneer::syscalls::winsize().ok().map(|d| d.ws_col as usize);
+        // Output that is not going to a terminal is one entry per line unless asked otherwise
+        if let DisplayMode::Grid(_) = app.display_mode {
+            app.display_mode = if stdout_size.is_some() || explicit_grid {
+                DisplayMode::Grid(width)
+            } else {
+                DisplayMode::SingleColumn
+            };
+        }
+
+        // Unless told how to treat symlinks, like GNU ls, show symlinks named on the command line
+        // as the links themselves whenever the listing would say what they are, and otherwise
+        // list the directories they point to
+        let dereference_given = given
+            .iter()
+            .any(|(id, _)| *id == Id::DereferenceCommandLine || *id == Id::Dereference);
+        if !dereference_given {
+            app.follow_symlinks = if app.display_mode == DisplayMode::Long
+                || !app.list_directory_contents
+                || app.suffixes == Suffixes::All
+            {
+                FollowSymlinks::Never
+            } else {
+                FollowSymlinks::DirectoriesWhenExplicit
+            };
+        }
 
-        match (terminal_width, app.display_mode) {
-            (Some(width), DisplayMode::Grid(_)) => app.display_mode = DisplayMode::Grid(width),
-            (None, DisplayMode::Grid(_)) => app.display_mode = DisplayMode::SingleColumn,
-            _ => {}
+        // The git column and dired offsets only exist in long listings
+        if app.display_mode != DisplayMode::Long {
+            app.show_git_status = false;
+            app.dired = None;
+        }
+
+        if color_enabled(color_when, stdout_size.is_some()) {
+            app.color = if given.iter().any(|(id, _)| *id == Id::FastColor) {
+                Color::Fast
+            } else {
+                Color::Full
+            };
         }
 
-        app.out = if terminal_width.is_some() {
+        app.out = if stdout_size.is_some() {
             BufferedStdout::terminal()
         } else {
             BufferedStdout::file()
         };
+        app.out.set_styled(app.color != Color::Never);
+        if app.color != Color::Never {
+            app.out.set_depth(crate::style::Depth::detect());
+            app.theme = Theme::load(preset, !no_config);
+            app.theme.scale_sizes = matches!(scale, Some(b"all") | Some(b"size"));
+            app.theme.scale_ages = matches!(scale, Some(b"all") | Some(b"age"));
+        }
+
+        // Reading ahead only pays off when there are subdirectories to read
+        if let (Some(threads), true) = (parallel, app.recurse) {
+            app.walker = crate::walk::Walker::new(threads);
+        }
 
         if !args_valid {
-            Err(veneer::Error(-1))
+            crate::diagnostics::try_help();
+            Err(crate::error::Error::Usage)
         } else {
             Ok(app)
         }
     }
 
+    /// Records a problem to be reflected in the exit status
+    pub fn report(&mut self, problem: crate::error::Error) {
+        self.problem = self.problem.max(Some(problem));
+    }
+
+    /// A problem with the directory being listed, which is serious only for one named on the
+    /// command line
+    pub fn directory_problem(&self) -> crate::error::Error {
+        if self.depth == 0 {
+            crate::error::Error::Serious
+        } else {
+            crate::error::Error::Minor
+        }
+    }
+
     pub fn convert_status(&self, status: libc::stat64) -> crate::Status {
         use TimeField::*;
         crate::Status {
@@ -295,12 +701,432 @@ impl App {
             uid: status.st_uid,
             gid: status.st_gid,
             inode: status.st_ino,
+            device: status.st_dev,
+            mtime: status.st_mtime,
+            mtime_nsec: status.st_mtime_nsec,
             time: match self.time_field {
                 Accessed => status.st_atime,
-                Created => status.st_ctime,
                 Modified => status.st_mtime,
-                StatusModified => status.st_mtime,
+                StatusModified => status.st_ctime,
             },
         }
     }
 }
+
+fn parse_number(value: &[u8]) -> Option<usize> {
+    if value.is_empty() {
+        return None;
+    }
+    value.iter().try_fold(0usize, |width, b| {
+        if b.is_ascii_digit() {
+            width.checked_mul(10)?.checked_add((b - b'0') as usize)
+        } else {
+            None
+        }
+    })
+}
+
+/// Whether to colour at all. An explicit --color wins, then NO_COLOR, CLICOLOR_FORCE and CLICOLOR
+/// in that order, and otherwise only a terminal gets colour.
+fn color_enabled(when: Option<&[u8]>, stdout_is_terminal: bool) -> bool {
+    match when {
+        Some(b"always") => return true,
+        Some(b"never") => return false,
+        Some(_) => return stdout_is_terminal,
+        None => {}
+    }
+    if matches!(crate::fs::getenv(b"NO_COLOR\0"), Some(v) if !v.is_empty()) {
+        return false;
+    }
+    if matches!(crate::fs::getenv(b"CLICOLOR_FORCE\0"), Some(v) if !v.is_empty() && v != b"0") {
+        return true;
+    }
+    if crate::fs::getenv(b"CLICOLOR\0") == Some(b"0") {
+        return false;
+    }
+    stdout_is_terminal
+}
+
+fn stderr_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { core::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_col > 0
+    {
+        Some(size.ws_col as usize)
+    } else {
+        None
+    }
+}
+
+/// Parses the options in `words`, adding anything that is not an option to `operands`. `origin`
+/// says where the words came from at the start of each message.
+fn parse_words(
+    mut words: impl Iterator<Item = CStr<'static>>,
+    origin: &[u8],
+    given: &mut Vec<(Id, Option<&'static [u8]>)>,
+    operands: &mut Vec<CStr<'static>>,
+) -> bool {
+    let mut valid = true;
+    let mut hit_only_arg_marker = false;
+    while let Some(arg) = words.next() {
+        let bytes = arg.as_bytes();
+        if bytes == b"--" {
+            hit_only_arg_marker = true;
+        } else if hit_only_arg_marker {
+            operands.push(arg);
+        // Things like --color=always
+        } else if bytes.starts_with(b"--") {
+            let (name, value) = match bytes.iter().position(|b| *b == b'=') {
+                Some(p) => (&bytes[2..p], Some(&bytes[p + 1..])),
+                None => (&bytes[2..], None),
+            };
+            let spec = match OPTIONS
+                .iter()
+                .find(|o| o.long.map(str::as_bytes) == Some(name))
+            {
+                Some(spec) => spec,
+                None => {
+                    error!(origin, b"unrecognized option ", quote(bytes));
+                    if let Some(suggestion) = closest_long(name) {
+                        crate::diagnostics::suggest(b"--", suggestion.as_bytes());
+                    }
+                    valid = false;
+                    continue;
+                }
+            };
+            let value = match (spec.takes, value) {
+                (Takes::Nothing, Some(_)) => {
+                    error!(
+                        origin,
+                        b"option ",
+                        quote(&bytes[..name.len() + 2]),
+                        b" doesn't allow an argument"
+                    );
+                    valid = false;
+                    continue;
+                }
+                (Takes::Value, None) => words.next().map(|a| a.as_bytes()),
+                (_, value) => value,
+            };
+            if spec.takes == Takes::Value && value.is_none() {
+                error!(origin, b"option ", quote(bytes), b" requires an argument");
+                valid = false;
+                continue;
+            }
+            valid &= check_value(origin, spec, value);
+            given.push((spec.id, value));
+        // Things like -R
+        } else if bytes.len() > 1 && bytes[0] == b'-' {
+            for (i, switch) in bytes.iter().cloned().enumerate().skip(1) {
+                let spec = match OPTIONS.iter().find(|o| o.short == Some(switch)) {
+                    Some(spec) => spec,
+                    None => {
+                        error!(origin, b"invalid option -- ", quote(&[switch]));
+                        if let Some(suggestion) = closest_long(&bytes[1..]) {
+                            crate::diagnostics::suggest(b"--", suggestion.as_bytes());
+                        } else if let Some(other_case) = OPTIONS
+                            .iter()
+                            .filter_map(|o| o.short)
+                            .find(|s| s.eq_ignore_ascii_case(&switch))
+                        {
+                            crate::diagnostics::suggest(b"-", &[other_case]);
+                        }
+                        // The rest of a mistyped argument is likely meaningless too
+                        valid = false;
+                        break;
+                    }
+                };
+                // An option with a value takes the rest of this argument or the whole next one
+                if spec.takes == Takes::Value {
+                    let value = if i + 1 < bytes.len() {
+                        Some(&bytes[i + 1..])
+                    } else {
+                        words.next().map(|a| a.as_bytes())
+                    };
+                    match value {
+                        Some(value) => {
+                            valid &= check_value(origin, spec, Some(value));
+                            given.push((spec.id, Some(value)));
+                        }
+                        None => {
+                            error!(origin, b"option requires an argument -- ", quote(&[switch]));
+                            valid = false;
+                        }
+                    }
+                    break;
+                }
+                given.push((spec.id, None));
+            }
+        } else {
+            operands.push(arg);
+        }
+    }
+    valid
+}
+
+/// Reports a value that an option does not accept
+fn check_value(origin: &[u8], spec: &OptionSpec, value: Option<&[u8]>) -> bool {
+    let value = match value {
+        Some(value) => value,
+        None => return true,
+    };
+    match spec.id {
+        Id::Width if parse_number(value).is_none() => {
+            error!(origin, b"invalid line width: ", quote(value));
+            false
+        }
+        Id::Parallel if parse_number(value).is_none() => {
+            crate::diagnostics::invalid_argument(origin, value, b"parallel");
+            false
+        }
+        Id::SkipFs => {
+            let mut valid = true;
+            for filesystem in value.split(|b| *b == b',').filter(|f| !f.is_empty()) {
+                if crate::fs::filesystem_magic(filesystem).is_none() {
+                    error!(origin, b"unknown filesystem type ", quote(filesystem));
+                    valid = false;
+                }
+            }
+            valid
+        }
+        _ if !spec.choices.is_empty() && !spec.choices.iter().any(|c| c.as_bytes() == value) => {
+            let option = spec.long.unwrap_or_default().as_bytes();
+            crate::diagnostics::invalid_argument(origin, value, option);
+            crate::diagnostics::valid_arguments(spec.choices);
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Reads default options from the config file and then from FLS_OPTIONS. Both hold options
+/// separated by whitespace, and lines of the config file that start with # are comments.
+fn parse_defaults(given: &mut Vec<(Id, Option<&'static [u8]>)>) -> bool {
+    let mut valid = true;
+    let mut operands = Vec::new();
+
+    if let Some(path) = config_file(b"config") {
+        if let Some(contents) = crate::fs::read(&path) {
+            for (i, line) in contents.split(|b| *b == b'\n').enumerate() {
+                if line.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'#') {
+                    continue;
+                }
+                let mut origin = path.clone();
+                origin.push(b':');
+                origin.extend_from_slice(itoa::Buffer::new().format(i + 1).as_bytes());
+                origin.extend_from_slice(b": ");
+                let start = given.len();
+                valid &= parse_words(split_words(line).into_iter(), &origin, given, &mut operands);
+                valid &= remove_exiting_options(&origin, given, start);
+                for operand in operands.drain(..) {
+                    error!(&origin, b"not an option: ", quote(operand.as_bytes()));
+                    valid = false;
+                }
+            }
+        }
+    }
+
+    if let Some(options) = crate::fs::getenv(b"FLS_OPTIONS\0") {
+        let origin = b"FLS_OPTIONS: ";
+        let start = given.len();
+        valid &= parse_words(
+            split_words(options).into_iter(),
+            origin,
+            given,
+            &mut operands,
+        );
+        valid &= remove_exiting_options(origin, given, start);
+        for operand in operands.drain(..) {
+            error!(origin, b"not an option: ", quote(operand.as_bytes()));
+            valid = false;
+        }
+    }
+
+    valid
+}
+
+/// Removes the options from `start` on that print something and exit instead of listing, which
+/// would make every run do just that if they came from the defaults
+fn remove_exiting_options(
+    origin: &[u8],
+    given: &mut Vec<(Id, Option<&'static [u8]>)>,
+    start: usize,
+) -> bool {
+    let mut valid = true;
+    let mut i = start;
+    while i < given.len() {
+        let (id, _) = given[i];
+        if !matches!(id, Id::Help | Id::Version | Id::Completions) {
+            i += 1;
+            continue;
+        }
+        given.remove(i);
+        if let Some(long) = OPTIONS.iter().find(|o| o.id == id).and_then(|o| o.long) {
+            let mut name = b"--".to_vec();
+            name.extend_from_slice(long.as_bytes());
+            error!(
+                origin,
+                b"option ",
+                quote(&name),
+                b" is only allowed on the command line"
+            );
+        }
+        valid = false;
+    }
+    valid
+}
+
+/// $XDG_CONFIG_HOME/fls/`name`, where $XDG_CONFIG_HOME defaults to ~/.config
+pub fn config_file(name: &[u8]) -> Option<Vec<u8>> {
+    let mut path = match crate::fs::getenv(b"XDG_CONFIG_HOME\0") {
+        // Relative paths are to be ignored, according to the XDG spec
+        Some(dir) if dir.first() == Some(&b'/') => dir.to_vec(),
+        _ => {
+            let mut path = crate::fs::getenv(b"HOME\0")?.to_vec();
+            path.extend_from_slice(b"/.config");
+            path
+        }
+    };
+    path.extend_from_slice(b"/fls/");
+    path.extend_from_slice(name);
+    Some(path)
+}
+
+/// Splits `text` at whitespace into words that can be parsed like command line arguments. Option
+/// values point into the words for the rest of the run, so their storage is never freed.
+fn split_words(text: &[u8]) -> Vec<CStr<'static>> {
+    let mut owned = text.to_vec();
+    owned.push(0);
+    for b in owned.iter_mut().filter(|b| b.is_ascii_whitespace()) {
+        *b = 0;
+    }
+    let owned: &'static [u8] = owned.leak();
+
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, b) in owned.iter().enumerate() {
+        if *b == 0 {
+            if i > start {
+                words.push(CStr::from_bytes(&owned[start..=i]));
+            }
+            start = i + 1;
+        }
+    }
+    words
+}
+
+/// The long option that a mistyped `name` was most likely meant to be, if any is close
+fn closest_long(name: &[u8]) -> Option<&'static str> {
+    let longs = OPTIONS.iter().filter_map(|o| o.long);
+    if name.len() >= 2 {
+        if let Some(long) = longs.clone().find(|l| l.as_bytes().starts_with(name)) {
+            return Some(long);
+        }
+    }
+    longs
+        .map(|l| (edit_distance(name, l.as_bytes()), l))
+        .min_by_key(|(distance, _)| *distance)
+        .filter(|(distance, _)| *distance <= (name.len() / 3).max(1))
+        .map(|(_, l)| l)
+}
+
+/// Levenshtein distance, keeping only two rows of the table
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = alloc::vec![0; b.len() + 1];
+    for (i, x) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous[j] + (x != y) as usize;
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        core::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Where descriptions start in --help
+const HELP_COLUMN: usize = 32;
+
+fn write_help() {
+    let mut out = BufferedStdout::file();
+    out.write(b"Usage: fls [OPTION]... [FILE]...\n")
+        .write(b"List information about the FILEs (the current directory by default).\n");
+    for category in Category::ALL.iter().cloned() {
+        out.push(b'\n')
+            .write(category.title().as_bytes())
+            .write(b":\n");
+        for spec in OPTIONS.iter().filter(|o| o.category == category) {
+            let start = out.position();
+            out.pad(2);
+            match spec.short {
+                Some(short) => out.push(b'-').push(short),
+                None => out.pad(2),
+            };
+            if let Some(long) = spec.long {
+                out.write(if spec.short.is_some() {
+                    b", --"
+                } else {
+                    b"  --"
+                })
+                .write(long.as_bytes());
+            }
+            match spec.takes {
+                Takes::Nothing => {}
+                Takes::Value if spec.long.is_none() => {
+                    out.push(b' ').write(spec.value_name.as_bytes());
+                }
+                Takes::Value => {
+                    out.push(b'=').write(spec.value_name.as_bytes());
+                }
+                Takes::OptionalValue => {
+                    out.write(b"[=")
+                        .write(spec.value_name.as_bytes())
+                        .push(b']');
+                }
+            }
+            let used = out.position() - start;
+            if used + 2 > HELP_COLUMN {
+                out.push(b'\n').pad(HELP_COLUMN);
+            } else {
+                out.pad(HELP_COLUMN - used);
+            }
+            out.write(spec.help.as_bytes()).push(b'\n');
+
+            if !spec.choices.is_empty() {
+                out.pad(HELP_COLUMN)
+                    .write(spec.value_name.as_bytes())
+                    .write(b" is ");
+                for (i, choice) in spec.choices.iter().enumerate() {
+                    if i + 1 == spec.choices.len() && i > 0 {
+                        out.write(b" or ");
+                    } else if i > 0 {
+                        out.write(b", ");
+                    }
+                    out.write(choice.as_bytes());
+                }
+                out.push(b'\n');
+            }
+        }
+    }
+}
+
+/// The optional parts of fls named in Cargo.toml, and whether this build has them
+const FEATURES: &[(&str, bool)] = &[
+    ("git", cfg!(feature = "git")),
+    ("io-uring", cfg!(feature = "io-uring")),
+    ("parallel", cfg!(feature = "parallel")),
+];
+
+fn write_version() {
+    let mut out = BufferedStdout::file();
+    out.write(b"fls ")
+        .write(env!("CARGO_PKG_VERSION").as_bytes())
+        .push(b'\n')
+        .write(b"Features:");
+    for (feature, enabled) in FEATURES {
+        out.write(if *enabled { b" +" } else { b" -" })
+            .write(feature.as_bytes());
+    }
+    out.push(b'\n');
+}
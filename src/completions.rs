@@ -0,0 +1,202 @@
+//! Shell completion scripts, written from the option table so they always offer exactly the
+//! options fls understands
+
+use crate::cli::{OptionSpec, Takes, OPTIONS};
+use crate::output::BufferedStdout;
+
+/// Writes the completion script for `shell`, which is one of the choices of --completions
+pub fn write(shell: &[u8]) {
+    let mut out = BufferedStdout::file();
+    match shell {
+        b"zsh" => write_zsh(&mut out),
+        b"fish" => write_fish(&mut out),
+        _ => write_bash(&mut out),
+    }
+}
+
+fn write_bash(out: &mut BufferedStdout) {
+    out.write(b"# bash completion for fls\n")
+        .write(b"_fls() {\n")
+        .write(b"    local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]} option\n")
+        // bash splits words at =, so the option a value belongs to may be two words back
+        .write(b"    if [[ $cur == = ]]; then\n")
+        .write(b"        option=$prev=\n")
+        .write(b"        cur=\n")
+        .write(b"    elif [[ $prev == = ]]; then\n")
+        .write(b"        option=${COMP_WORDS[COMP_CWORD-2]}=\n")
+        .write(b"    else\n")
+        .write(b"        option=$prev\n")
+        .write(b"    fi\n")
+        .write(b"    case $option in\n");
+    for spec in OPTIONS.iter().filter(|o| !o.choices.is_empty()) {
+        let long = match spec.long {
+            Some(long) => long.as_bytes(),
+            None => continue,
+        };
+        out.write(b"        --").write(long).write(b"=");
+        // Only a required value can be the next word
+        if spec.takes == Takes::Value {
+            out.write(b"|--").write(long);
+        }
+        out.write(b")\n")
+            .write(b"            COMPREPLY=($(compgen -W '");
+        write_words(out, spec.choices);
+        out.write(b"' -- \"$cur\"))\n")
+            .write(b"            return\n")
+            .write(b"            ;;\n");
+    }
+    out.write(b"    esac\n")
+        .write(b"    if [[ $cur == -* ]]; then\n")
+        .write(b"        COMPREPLY=($(compgen -W '");
+    let mut first = true;
+    for spec in OPTIONS {
+        let mut word = |out: &mut BufferedStdout, dashes: &[u8], name: &[u8], suffix: &[u8]| {
+            if !first {
+                out.push(b' ');
+            }
+            first = false;
+            out.write(dashes).write(name).write(suffix);
+        };
+        if let Some(short) = spec.short {
+            word(out, b"-", &[short], b"");
+        }
+        if let Some(long) = spec.long {
+            match spec.takes {
+                Takes::Nothing => word(out, b"--", long.as_bytes(), b""),
+                Takes::Value => word(out, b"--", long.as_bytes(), b"="),
+                Takes::OptionalValue => {
+                    word(out, b"--", long.as_bytes(), b"");
+                    word(out, b"--", long.as_bytes(), b"=");
+                }
+            }
+        }
+    }
+    out.write(b"' -- \"$cur\"))\n")
+        .write(b"        [[ ${COMPREPLY-} == *= ]] && compopt -o nospace\n")
+        .write(b"        return\n")
+        .write(b"    fi\n")
+        .write(b"    COMPREPLY=($(compgen -f -- \"$cur\"))\n")
+        .write(b"}\n")
+        .write(b"complete -o filenames -F _fls fls\n");
+}
+
+fn write_zsh(out: &mut BufferedStdout) {
+    out.write(b"#compdef fls\n\n_arguments -s -S \\\n");
+    for spec in OPTIONS {
+        out.write(b"  ");
+        match (spec.short, spec.long) {
+            // Both spellings share one description when they take the same value
+            (Some(short), Some(long)) if spec.takes != Takes::OptionalValue => {
+                out.write(b"{-").push(short);
+                if spec.takes == Takes::Value {
+                    out.push(b'+');
+                }
+                out.write(b",--").write(long.as_bytes());
+                if spec.takes == Takes::Value {
+                    out.push(b'=');
+                }
+                out.push(b'}');
+                write_zsh_description(out, spec, spec.takes);
+            }
+            (short, long) => {
+                // A short option never takes an optional value
+                if let Some(short) = short {
+                    out.write(b"-").push(short);
+                    write_zsh_description(out, spec, Takes::Nothing);
+                    if long.is_some() {
+                        out.write(b" \\\n  ");
+                    }
+                }
+                if let Some(long) = long {
+                    out.write(b"--").write(long.as_bytes());
+                    match spec.takes {
+                        Takes::Nothing => {}
+                        Takes::Value => {
+                            out.push(b'=');
+                        }
+                        Takes::OptionalValue => {
+                            out.write(b"=-");
+                        }
+                    }
+                    write_zsh_description(out, spec, spec.takes);
+                }
+            }
+        }
+        out.write(b" \\\n");
+    }
+    out.write(b"  '*:file:_files'\n");
+}
+
+/// The quoted `[description]:value:(choices)` part of an _arguments spec
+fn write_zsh_description(out: &mut BufferedStdout, spec: &OptionSpec, takes: Takes) {
+    out.write(b"'[");
+    for b in spec.help.bytes() {
+        match b {
+            b'[' | b']' | b'\\' | b':' => {
+                out.push(b'\\').push(b);
+            }
+            b'\'' => {
+                out.write(b"'\\''");
+            }
+            _ => {
+                out.push(b);
+            }
+        }
+    }
+    out.push(b']');
+    if takes != Takes::Nothing {
+        out.write(if takes == Takes::Value { b":" } else { b"::" })
+            .write(spec.value_name.as_bytes())
+            .push(b':');
+        if !spec.choices.is_empty() {
+            out.push(b'(');
+            write_words(out, spec.choices);
+            out.push(b')');
+        }
+    }
+    out.push(b'\'');
+}
+
+fn write_fish(out: &mut BufferedStdout) {
+    for spec in OPTIONS {
+        out.write(b"complete -c fls");
+        if let Some(short) = spec.short {
+            out.write(b" -s ").push(short);
+        }
+        if let Some(long) = spec.long {
+            out.write(b" -l ").write(long.as_bytes());
+        }
+        match spec.takes {
+            Takes::Nothing => {}
+            // No value fls takes is a file name
+            Takes::Value => {
+                out.write(b" -x");
+            }
+            Takes::OptionalValue => {
+                out.write(b" -f");
+            }
+        }
+        if !spec.choices.is_empty() {
+            out.write(b" -a '");
+            write_words(out, spec.choices);
+            out.push(b'\'');
+        }
+        out.write(b" -d '");
+        for b in spec.help.bytes() {
+            if b == b'\'' || b == b'\\' {
+                out.push(b'\\');
+            }
+            out.push(b);
+        }
+        out.write(b"'\n");
+    }
+}
+
+fn write_words(out: &mut BufferedStdout, words: &[&str]) {
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            out.push(b' ');
+        }
+        out.write(word.as_bytes());
+    }
+}
@@ -0,0 +1,203 @@
+//! Messages about problems, worded and quoted the way GNU ls words them, so people and scripts
+//! that know those are not surprised
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use veneer::{CStr, Error};
+
+static LOCALE_LOADED: AtomicBool = AtomicBool::new(false);
+static UTF8: AtomicBool = AtomicBool::new(false);
+
+/// Loading the locale is not free, so it only happens once something has gone wrong
+fn load_locale() {
+    if !LOCALE_LOADED.swap(true, Ordering::Relaxed) {
+        // Translations are only used when the character set they need is loaded too
+        for category in &[libc::LC_CTYPE, libc::LC_MESSAGES] {
+            unsafe { libc::setlocale(*category, b"\0".as_ptr() as *const libc::c_char) };
+        }
+        let codeset = unsafe { CStr::from_ptr(libc::nl_langinfo(libc::CODESET)) };
+        UTF8.store(codeset.as_bytes() == b"UTF-8", Ordering::Relaxed);
+    }
+}
+
+/// The description of an errno in the user's language
+pub fn strerror(err: Error) -> &'static [u8] {
+    load_locale();
+    unsafe { CStr::from_ptr(libc::strerror(err.0 as libc::c_int)).as_bytes() }
+}
+
+/// Splits a name into characters of the user's character set, each with whether it can be shown
+/// as it is. Bytes that are not part of a character come out on their own, as unprintable.
+fn characters(name: &[u8]) -> impl Iterator<Item = (&[u8], bool)> {
+    load_locale();
+    let utf8 = UTF8.load(Ordering::Relaxed);
+    let mut rest = name;
+    core::iter::from_fn(move || {
+        let b = *rest.first()?;
+        let (len, printable) = if b < 0x80 {
+            (1, !b.is_ascii_control())
+        } else if !utf8 {
+            (1, unsafe { libc::isprint(b as libc::c_int) } != 0)
+        } else {
+            let len = match b {
+                0xc2..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf4 => 4,
+                _ => 1,
+            };
+            match rest.get(..len).map(core::str::from_utf8) {
+                // C1 controls are the only characters outside ASCII a terminal cannot show
+                Some(Ok(c)) if c.chars().all(|c| c >= '\u{a0}') => (len, true),
+                _ => (1, false),
+            }
+        };
+        let (c, tail) = rest.split_at(len);
+        rest = tail;
+        Some((c, printable))
+    })
+}
+
+/// Quotes a name so it can be pasted back into a shell, as GNU ls does in its messages: in single
+/// quotes, in double quotes if it contains single quotes but nothing else special, and with
+/// what cannot be shown spelled out as `$'\n'`.
+pub fn quote(name: &[u8]) -> Vec<u8> {
+    let mut quoted = Vec::with_capacity(name.len() + 2);
+    let has_unprintable = characters(name).any(|(_, printable)| !printable);
+    if !has_unprintable && !name.contains(&b'\'') {
+        quoted.push(b'\'');
+        quoted.extend_from_slice(name);
+        quoted.push(b'\'');
+        return quoted;
+    }
+    if !has_unprintable && !name.iter().any(|b| b"\"$`\\".contains(b)) {
+        quoted.push(b'"');
+        quoted.extend_from_slice(name);
+        quoted.push(b'"');
+        return quoted;
+    }
+
+    #[derive(PartialEq)]
+    enum Quotes {
+        Single,
+        Escapes,
+        None,
+    }
+    quoted.push(b'\'');
+    let mut open = Quotes::Single;
+    for (c, printable) in characters(name) {
+        let wanted = if !printable {
+            Quotes::Escapes
+        } else if c == b"'" {
+            Quotes::None
+        } else {
+            Quotes::Single
+        };
+        if wanted != open {
+            if open != Quotes::None {
+                quoted.push(b'\'');
+            }
+            match wanted {
+                Quotes::Single => quoted.push(b'\''),
+                Quotes::Escapes => quoted.extend_from_slice(b"$'"),
+                Quotes::None => {}
+            }
+            open = wanted;
+        }
+        match open {
+            Quotes::Escapes => c.iter().for_each(|b| escape(*b, &mut quoted)),
+            Quotes::None => quoted.extend_from_slice(b"\\'"),
+            Quotes::Single => quoted.extend_from_slice(c),
+        }
+    }
+    if open != Quotes::None {
+        quoted.push(b'\'');
+    }
+    quoted
+}
+
+/// Like `quote`, but leaves alone names that a shell would take literally anyway
+pub fn quote_if_needed(name: &[u8]) -> Vec<u8> {
+    let plain = !name.is_empty()
+        && characters(name).all(|(c, printable)| {
+            printable
+                && (c[0] >= 0x80 || c[0].is_ascii_alphanumeric() || b"%+,-./:=@_".contains(&c[0]))
+        });
+    if plain {
+        name.to_vec()
+    } else {
+        quote(name)
+    }
+}
+
+fn escape(b: u8, out: &mut Vec<u8>) {
+    out.push(b'\\');
+    match b {
+        0x07 => out.push(b'a'),
+        0x08 => out.push(b'b'),
+        b'\t' => out.push(b't'),
+        b'\n' => out.push(b'n'),
+        0x0b => out.push(b'v'),
+        0x0c => out.push(b'f'),
+        b'\r' => out.push(b'r'),
+        _ => out.extend_from_slice(&[b'0' + (b >> 6), b'0' + ((b >> 3) & 7), b'0' + (b & 7)]),
+    }
+}
+
+pub fn cannot_access(path: &[u8], err: Error) {
+    error!(b"cannot access ", quote(path), b": ", strerror(err));
+}
+
+pub fn cannot_open_directory(path: &[u8], err: Error) {
+    error!(b"cannot open directory ", quote(path), b": ", strerror(err));
+}
+
+pub fn cannot_read_directory(path: &[u8], err: Error) {
+    error!(b"reading directory ", quote(path), b": ", strerror(err));
+}
+
+pub fn already_listed(path: &[u8]) {
+    error!(
+        quote_if_needed(path),
+        b": not listing already-listed directory"
+    );
+}
+
+/// `option` is the name of a long option without its leading dashes, and `origin` says where it
+/// was given if not on the command line
+pub fn invalid_argument(origin: &[u8], value: &[u8], option: &[u8]) {
+    let mut option_name = b"--".to_vec();
+    option_name.extend_from_slice(option);
+    error!(
+        origin,
+        b"invalid argument ",
+        quote(value),
+        b" for ",
+        quote(&option_name)
+    );
+}
+
+/// Lists the values an option accepts, after `invalid_argument`
+pub fn valid_arguments(choices: &[&str]) {
+    let mut message = b"Valid arguments are:\n".to_vec();
+    for choice in choices {
+        message.extend_from_slice(b"  - ");
+        message.extend(quote(choice.as_bytes()));
+        message.push(b'\n');
+    }
+    let _ = veneer::syscalls::write(2, &message);
+}
+
+/// Points at the option that a mistyped one was probably meant to be
+pub fn suggest(dashes: &[u8], name: &[u8]) {
+    let mut option = dashes.to_vec();
+    option.extend_from_slice(name);
+    let mut message = b"Did you mean ".to_vec();
+    message.extend(quote(&option));
+    message.extend_from_slice(b"?\n");
+    let _ = veneer::syscalls::write(2, &message);
+}
+
+/// Ends the messages about a command line that could not be understood
+pub fn try_help() {
+    let _ = veneer::syscalls::write(2, b"Try 'fls --help' for more information.\n");
+}
@@ -1,11 +1,11 @@
 use crate::cli::{App, Color, FollowSymlinks};
+use crate::fs::{DType, LazyMetadata, Metadata};
 use crate::Style;
-use veneer::directory::DType;
 use veneer::{syscalls, CStr};
 
 pub trait DirEntry {
     fn name(&self) -> CStr;
-    fn style(&self, dir: &veneer::Directory, app: &App) -> (Style, Option<u8>);
+    fn style(&self, dir: &crate::fs::Directory, app: &App) -> (Style, Option<u8>);
     fn inode(&self) -> u64;
     fn blocks(&self) -> u64;
 }
@@ -23,6 +23,21 @@ pub enum EntryType {
 }
 
 impl EntryType {
+    fn from_mode(mode: libc::mode_t) -> Self {
+        use EntryType::*;
+        match mode & libc::S_IFMT {
+            libc::S_IFDIR => Directory,
+            libc::S_IFLNK => Link,
+            libc::S_IFIFO => Fifo,
+            libc::S_IFSOCK => Socket,
+            libc::S_IFREG if mode & (libc::S_IXUSR | libc::S_IXGRP | libc::S_IXOTH) != 0 => {
+                Executable
+            }
+            libc::S_IFREG => Regular,
+            _ => Other,
+        }
+    }
+
     fn style(self, app: &App) -> (Option<Style>, Option<u8>) {
         use crate::cli::Suffixes;
         use EntryType::*;
@@ -33,19 +48,34 @@ impl EntryType {
             (Executable, Suffixes::All) => (Some(GreenBold), Some(b'*')),
             (Executable, _) => (Some(GreenBold), None),
             (Regular, _) => (None, None),
-            (Link, Suffixes::All) => (Some(CyanBold), Some(b'@')),
+            (Link, Suffixes::All) | (Link, Suffixes::FileType) => (Some(CyanBold), Some(b'@')),
             (Link, _) => (Some(CyanBold), None),
-            (BrokenLink, Suffixes::All) => (Some(RedBold), Some(b'@')),
+            (BrokenLink, Suffixes::All) | (BrokenLink, Suffixes::FileType) => {
+                (Some(RedBold), Some(b'@'))
+            }
             (BrokenLink, _) => (Some(RedBold), None),
-            (Fifo, Suffixes::All) => (Some(YellowBold), Some(b'|')),
+            (Fifo, Suffixes::All) | (Fifo, Suffixes::FileType) => (Some(YellowBold), Some(b'|')),
             (Fifo, _) => (Some(YellowBold), None),
+            (Socket, Suffixes::All) | (Socket, Suffixes::FileType) => {
+                (Some(MagentaBold), Some(b'='))
+            }
             (Socket, _) => (Some(MagentaBold), None),
             (Other, _) => (Some(YellowBold), None),
         }
     }
+
+    /// The style and indicator to print a name with, falling back to the extension for files
+    /// that have no colour of their own
+    fn finish(self, name: CStr, app: &App) -> (Style, Option<u8>) {
+        match (app.color, self.style(app)) {
+            (Color::Never, (_, suffix)) => (Style::White, suffix),
+            (_, (Some(style), suffix)) => (style, suffix),
+            (_, (None, suffix)) => (extension_style(name.as_bytes()), suffix),
+        }
+    }
 }
 
-impl<'a> DirEntry for veneer::directory::DirEntry<'a> {
+impl<'a> DirEntry for crate::fs::Entry<'a> {
     fn name(&self) -> CStr {
         self.name()
     }
@@ -55,76 +85,78 @@ impl<'a> DirEntry for veneer::directory::DirEntry<'a> {
     }
 
     fn blocks(&self) -> u64 {
-        0
+        self.metadata().map(|m| m.blocks).unwrap_or(0)
     }
 
-    fn style(&self, dir: &veneer::Directory, app: &App) -> (Style, Option<u8>) {
+    fn style(&self, dir: &crate::fs::Directory, app: &App) -> (Style, Option<u8>) {
+        use crate::cli::Suffixes;
         use EntryType::*;
-        if app.color == Color::Never {
+        if app.color == Color::Never && app.suffixes == Suffixes::None {
             return (Style::White, None);
-        } else if app.color == Color::Auto {
-            let (style, suffix) = match self.d_type() {
-                DType::DIR => Directory,
-                DType::FIFO => Fifo,
-                DType::SOCK => Socket,
-                DType::CHR | DType::BLK => Other,
-                DType::LNK => Link,
-                DType::REG | DType::UNKNOWN => Regular,
-            }
-            .style(app);
-
-            return if let Some(style) = style {
-                (style, suffix)
-            } else {
-                (extension_style(self.name().as_bytes()), suffix)
-            };
         }
-        let (style, suffix) = match self.d_type() {
+
+        // d_type is enough for most entries. Executables need the mode bits and broken links
+        // need a lookup of their target, but only when the colour or indicator would show it.
+        let full_color = app.color == Color::Full;
+        let target_exists = || syscalls::faccessat(dir.raw_fd(), self.name(), libc::F_OK).is_ok();
+        // With -L a link looks like whatever it points to
+        let follow = app.follow_symlinks == FollowSymlinks::Always;
+        let target_type = || {
+            syscalls::fstatat(dir.raw_fd(), self.name())
+                .map(|status| EntryType::from_mode(status.st_mode))
+                .unwrap_or(BrokenLink)
+        };
+        let entry_type = match self.d_type() {
             DType::DIR => Directory,
             DType::FIFO => Fifo,
             DType::SOCK => Socket,
             DType::CHR | DType::BLK => Other,
-            DType::REG => syscalls::faccessat(dir.raw_fd(), self.name(), libc::X_OK)
-                .map(|_| Executable)
+            DType::REG if full_color || app.suffixes == Suffixes::All => self
+                .metadata()
+                .map(|m| EntryType::from_mode(m.mode))
                 .unwrap_or(Regular),
-            DType::LNK => syscalls::faccessat(dir.raw_fd(), self.name(), libc::F_OK)
-                .map(|_| Link)
-                .unwrap_or(BrokenLink),
-            DType::UNKNOWN => if app.follow_symlinks == FollowSymlinks::Always {
-                syscalls::fstatat(dir.raw_fd(), self.name())
-            } else {
-                syscalls::lstatat(dir.raw_fd(), self.name())
-            }
-            .map(|status| {
-                let status = app.convert_status(status);
-                let entry_type = status.mode & libc::S_IFMT;
-                if entry_type == libc::S_IFDIR {
-                    Directory
-                } else if entry_type == libc::S_IFIFO {
-                    Fifo
-                } else if entry_type == libc::S_IFLNK {
-                    Link
-                } else if status.mode & libc::S_IXUSR > 0 {
-                    Executable
-                } else {
-                    Regular
+            DType::REG => Regular,
+            DType::LNK if follow => target_type(),
+            DType::LNK if full_color && target_exists() => Link,
+            DType::LNK if full_color => BrokenLink,
+            DType::LNK => Link,
+            DType::UNKNOWN if full_color || app.suffixes != Suffixes::None => {
+                match self.metadata().map(|m| EntryType::from_mode(m.mode)) {
+                    Some(Link) if follow => target_type(),
+                    Some(entry_type) => entry_type,
+                    None => BrokenLink,
                 }
-            })
-            .unwrap_or(BrokenLink),
-        }
-        .style(app);
+            }
+            DType::UNKNOWN => Regular,
+        };
 
-        if let Some(style) = style {
-            (style, suffix)
-        } else {
-            (extension_style(self.name().as_bytes()), suffix)
-        }
+        entry_type.finish(self.name(), app)
     }
 }
 
+/// A path named on the command line, relative to the working directory
 #[derive(Clone)]
 pub struct File<'a> {
     pub path: CStr<'a>,
+    follow: bool,
+    metadata: LazyMetadata,
+}
+
+impl<'a> File<'a> {
+    pub fn new(path: CStr<'a>, app: &App) -> Self {
+        File {
+            path,
+            follow: matches!(
+                app.follow_symlinks,
+                FollowSymlinks::Always | FollowSymlinks::WhenExplicit
+            ),
+            metadata: LazyMetadata::default(),
+        }
+    }
+
+    fn metadata(&self) -> Option<Metadata> {
+        self.metadata.get(libc::AT_FDCWD, self.path, self.follow)
+    }
 }
 
 impl<'a> DirEntry for File<'a> {
@@ -133,45 +165,26 @@ impl<'a> DirEntry for File<'a> {
     }
 
     fn inode(&self) -> u64 {
-        0
+        self.metadata().map(|m| m.inode).unwrap_or(0)
     }
 
     fn blocks(&self) -> u64 {
-        0
+        self.metadata().map(|m| m.blocks).unwrap_or(0)
     }
 
-    fn style(&self, dir: &veneer::Directory, app: &App) -> (Style, Option<u8>) {
-        use EntryType::*;
-        if app.color == Color::Never {
-            return (Style::White, None);
-        } else if app.color == Color::Auto {
-            return (extension_style(self.name().as_bytes()), None);
-        }
-        let entry_type = if app.follow_symlinks == FollowSymlinks::Always {
-            syscalls::fstatat(dir.raw_fd(), self.name()).map(|s| app.convert_status(s))
-        } else {
-            syscalls::lstatat(dir.raw_fd(), self.name()).map(|s| app.convert_status(s))
-        }
-        .map(|status| {
-            let entry_type = status.mode & libc::S_IFMT;
-            if entry_type == libc::S_IFDIR {
-                Directory
-            } else if entry_type == libc::S_IFIFO {
-                Fifo
-            } else if entry_type == libc::S_IFLNK {
-                Link
-            } else if status.mode & libc::S_IXUSR > 0 {
-                Executable
-            } else {
-                Regular
+    fn style(&self, _dir: &crate::fs::Directory, app: &App) -> (Style, Option<u8>) {
+        use crate::cli::Suffixes;
+        if app.suffixes == Suffixes::None {
+            match app.color {
+                Color::Never => return (Style::White, None),
+                Color::Fast => return (extension_style(self.name().as_bytes()), None),
+                Color::Full => {}
             }
-        })
-        .unwrap_or(BrokenLink);
-
-        match entry_type.style(app) {
-            (Some(style), suffix) => (style, suffix),
-            (None, suffix) => (extension_style(self.name().as_bytes()), suffix),
         }
+        self.metadata()
+            .map(|m| EntryType::from_mode(m.mode))
+            .unwrap_or(EntryType::BrokenLink)
+            .finish(self.name(), app)
     }
 }
 
@@ -208,30 +221,7 @@ where
         self.1.blocks as u64
     }
 
-    fn style(&self, _fd: &veneer::Directory, app: &App) -> (Style, Option<u8>) {
-        use EntryType::*;
-        let entry_type = self.1.mode & libc::S_IFMT;
-        let (style, suffix) = if entry_type == libc::S_IFDIR {
-            Directory
-        } else if entry_type == libc::S_IFIFO {
-            Fifo
-        } else if entry_type == libc::S_IFSOCK {
-            Socket
-        } else if entry_type == libc::S_IFLNK {
-            Link
-        } else if self.1.mode & libc::S_IXUSR > 0 {
-            Executable
-        } else if entry_type == libc::S_IFREG {
-            Regular
-        } else {
-            Other
-        }
-        .style(app);
-
-        if let Some(style) = style {
-            (style, suffix)
-        } else {
-            (extension_style(self.name().as_bytes()), suffix)
-        }
+    fn style(&self, _fd: &crate::fs::Directory, app: &App) -> (Style, Option<u8>) {
+        EntryType::from_mode(self.1.mode).finish(self.name(), app)
     }
 }
@@ -1,13 +1,21 @@
-pub struct Error(pub isize);
-
-impl From<i32> for Error {
-    fn from(e: i32) -> Error {
-        Error(e as isize)
-    }
+/// Why a run did not go cleanly, ordered by how much it matters. Like GNU ls, fls keeps going
+/// after most problems and reports the worst one through its exit status.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Error {
+    /// Something found inside a directory could not be accessed or listed
+    Minor,
+    /// A file or directory named on the command line could not be accessed or listed, or a
+    /// directory loop was found
+    Serious,
+    /// The command line could not be understood, so nothing was listed
+    Usage,
 }
 
-impl From<isize> for Error {
-    fn from(e: isize) -> Error {
-        Error(e)
+impl Error {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Error::Minor => 1,
+            Error::Serious | Error::Usage => 2,
+        }
     }
 }
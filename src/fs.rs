@@ -0,0 +1,470 @@
+use alloc::vec::Vec;
+use core::cell::Cell;
+use veneer::{syscalls, CStr, Error};
+
+/// Reads the whole file at `path` relative to `dirfd`. `path` must be nul-terminated.
+pub fn read_at(dirfd: libc::c_int, path: &[u8]) -> Option<Vec<u8>> {
+    debug_assert_eq!(path.last(), Some(&0));
+    let fd = unsafe {
+        libc::openat(
+            dirfd,
+            path.as_ptr() as *const libc::c_char,
+            libc::O_RDONLY | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return None;
+    }
+
+    let mut contents = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let ok = loop {
+        let n = unsafe { libc::read(fd, chunk.as_mut_ptr() as *mut libc::c_void, chunk.len()) };
+        match n {
+            0 => break true,
+            n if n < 0 => break false,
+            n => contents.extend_from_slice(&chunk[..n as usize]),
+        }
+    };
+
+    unsafe { libc::close(fd) };
+    if ok {
+        Some(contents)
+    } else {
+        None
+    }
+}
+
+/// Reads the whole file at `path`, which does not need to be nul-terminated
+pub fn read(path: &[u8]) -> Option<Vec<u8>> {
+    read_at(libc::AT_FDCWD, &nul_terminated(path))
+}
+
+pub fn exists(path: &[u8]) -> bool {
+    let path = nul_terminated(path);
+    unsafe { libc::access(path.as_ptr() as *const libc::c_char, libc::F_OK) == 0 }
+}
+
+/// Canonical absolute path of `path`, without a trailing '/' unless it is the root
+pub fn realpath(path: CStr) -> Option<Vec<u8>> {
+    unsafe {
+        let resolved = libc::realpath(
+            path.as_bytes().as_ptr() as *const libc::c_char,
+            core::ptr::null_mut(),
+        );
+        if resolved.is_null() {
+            return None;
+        }
+        let owned = CStr::from_ptr(resolved).as_bytes().to_vec();
+        libc::free(resolved as *mut libc::c_void);
+        Some(owned)
+    }
+}
+
+/// Looks up an environment variable. `name` must be nul-terminated.
+pub fn getenv(name: &[u8]) -> Option<&'static [u8]> {
+    debug_assert_eq!(name.last(), Some(&0));
+    unsafe {
+        let value = libc::getenv(name.as_ptr() as *const libc::c_char);
+        if value.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(value).as_bytes())
+        }
+    }
+}
+
+pub fn nul_terminated(path: &[u8]) -> Vec<u8> {
+    let mut owned = Vec::with_capacity(path.len() + 1);
+    owned.extend_from_slice(path);
+    owned.push(0);
+    owned
+}
+
+/// A read-only memory map of a whole file
+pub struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapping {
+    pub fn open(path: &[u8]) -> Option<Self> {
+        let path = nul_terminated(path);
+        unsafe {
+            let fd = libc::open(
+                path.as_ptr() as *const libc::c_char,
+                libc::O_RDONLY | libc::O_CLOEXEC,
+            );
+            if fd < 0 {
+                return None;
+            }
+            let mut status: libc::stat64 = core::mem::zeroed();
+            let len = if libc::fstat64(fd, &mut status) == 0 {
+                status.st_size as usize
+            } else {
+                0
+            };
+            let ptr = if len > 0 {
+                libc::mmap(
+                    core::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    fd,
+                    0,
+                )
+            } else {
+                libc::MAP_FAILED
+            };
+            libc::close(fd);
+            if ptr == libc::MAP_FAILED {
+                None
+            } else {
+                Some(Mapping { ptr, len })
+            }
+        }
+    }
+}
+
+impl core::ops::Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// Looks up the statfs magic number of a filesystem by the name it has in /proc/filesystems
+pub fn filesystem_magic(name: &[u8]) -> Option<u32> {
+    Some(match name {
+        b"autofs" => 0x0187,
+        b"binfmt_misc" => 0x4249_4e4d,
+        b"bpf" => 0xcafe_4a11,
+        b"btrfs" => 0x9123_683e,
+        b"cgroup" => 0x0027_e0eb,
+        b"cgroup2" => 0x6367_7270,
+        b"cifs" => 0xff53_4d42,
+        b"configfs" => 0x6265_6570,
+        b"debugfs" => 0x6462_6720,
+        b"devpts" => 0x1cd1,
+        b"efivarfs" => 0xde5e_81e4,
+        b"ext2" | b"ext3" | b"ext4" => 0xef53,
+        b"fuse" | b"fuseblk" => 0x6573_5546,
+        b"hugetlbfs" => 0x9584_58f6,
+        b"iso9660" => 0x9660,
+        b"mqueue" => 0x1980_0202,
+        b"nfs" | b"nfs4" => 0x6969,
+        b"overlay" => 0x794c_7630,
+        b"proc" => 0x9fa0,
+        b"pstore" => 0x6165_676c,
+        b"ramfs" => 0x8584_58f6,
+        b"securityfs" => 0x7363_6673,
+        b"smb2" | b"smb3" => 0xfe53_4d42,
+        b"squashfs" => 0x7371_7368,
+        b"sysfs" => 0x6265_6572,
+        b"tmpfs" | b"devtmpfs" => 0x0102_1994,
+        b"tracefs" => 0x7472_6163,
+        b"vfat" => 0x4d44,
+        b"xfs" => 0x5846_5342,
+        b"zfs" => 0x2fc1_2fc1,
+        _ => return None,
+    })
+}
+
+/// The magic number identifying the kind of filesystem that `name` inside `dirfd` is on. It is
+/// only opened as a path, which reads nothing and does not set off an automount.
+pub fn filesystem_type_at(dirfd: libc::c_int, name: CStr, follow: bool) -> Result<u32, Error> {
+    let mut flags = libc::O_PATH | libc::O_CLOEXEC;
+    if !follow {
+        flags |= libc::O_NOFOLLOW;
+    }
+    let fd = unsafe {
+        libc::openat(
+            dirfd,
+            name.as_bytes().as_ptr() as *const libc::c_char,
+            flags,
+        )
+    };
+    if fd < 0 {
+        return Err(last_error());
+    }
+    let mut status: libc::statfs64 = unsafe { core::mem::zeroed() };
+    let result = if unsafe { libc::fstatfs64(fd, &mut status) } == 0 {
+        // Magic numbers are 32 bits wide even where f_type is a signed long
+        Ok(status.f_type as u32)
+    } else {
+        Err(last_error())
+    };
+    unsafe { libc::close(fd) };
+    result
+}
+
+/// The type of a directory entry as reported by getdents, without a stat
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    FIFO,
+    CHR,
+    DIR,
+    BLK,
+    REG,
+    LNK,
+    SOCK,
+    UNKNOWN,
+}
+
+impl DType {
+    fn from_raw(d_type: u8) -> Self {
+        match d_type {
+            libc::DT_FIFO => DType::FIFO,
+            libc::DT_CHR => DType::CHR,
+            libc::DT_DIR => DType::DIR,
+            libc::DT_BLK => DType::BLK,
+            libc::DT_REG => DType::REG,
+            libc::DT_LNK => DType::LNK,
+            libc::DT_SOCK => DType::SOCK,
+            _ => DType::UNKNOWN,
+        }
+    }
+}
+
+fn last_error() -> Error {
+    Error(unsafe { *libc::__errno_location() } as isize)
+}
+
+/// An open directory. Subdirectories are opened relative to it, so the length of the path
+/// leading here never matters.
+pub struct Directory {
+    fd: libc::c_int,
+}
+
+impl Directory {
+    pub fn open(path: CStr) -> Result<Self, Error> {
+        let fd = unsafe {
+            libc::open(
+                path.as_bytes().as_ptr() as *const libc::c_char,
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            Err(last_error())
+        } else {
+            Ok(Directory { fd })
+        }
+    }
+
+    /// Opens the subdirectory `name` of `dirfd`, refusing to go through a symlink unless `follow`
+    /// is set
+    pub fn open_at(dirfd: libc::c_int, name: CStr, follow: bool) -> Result<Self, Error> {
+        let mut flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        if !follow {
+            flags |= libc::O_NOFOLLOW;
+        }
+        let fd = unsafe {
+            libc::openat(
+                dirfd,
+                name.as_bytes().as_ptr() as *const libc::c_char,
+                flags,
+            )
+        };
+        if fd < 0 {
+            Err(last_error())
+        } else {
+            Ok(Directory { fd })
+        }
+    }
+
+    pub fn raw_fd(&self) -> libc::c_int {
+        self.fd
+    }
+
+    pub fn status(&self) -> Result<libc::stat64, Error> {
+        let mut status: libc::stat64 = unsafe { core::mem::zeroed() };
+        if unsafe { libc::fstat64(self.fd, &mut status) } == 0 {
+            Ok(status)
+        } else {
+            Err(last_error())
+        }
+    }
+
+    /// Goes back to the first entry, so that the directory can be read again
+    pub fn rewind(&self) -> Result<(), Error> {
+        if unsafe { libc::lseek64(self.fd, 0, libc::SEEK_SET) } < 0 {
+            Err(last_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn read(&self) -> Result<Contents, Error> {
+        self.read_some(usize::MAX).map(|(contents, _)| contents)
+    }
+
+    /// Reads entries until the end of the directory or until about `limit` bytes of records
+    /// have been read. The flag is set if the end was reached; otherwise calling this again
+    /// carries on where it stopped.
+    pub fn read_some(&self, limit: usize) -> Result<(Contents, bool), Error> {
+        let mut buf: Vec<u8> = Vec::with_capacity(limit.min(32 * 1024));
+        loop {
+            if buf.len() >= limit {
+                return Ok((
+                    Contents {
+                        buf,
+                        dirfd: self.fd,
+                    },
+                    false,
+                ));
+            }
+            if buf.capacity() - buf.len() < 4096 {
+                buf.reserve(buf.capacity().min(limit - buf.len()).max(4096));
+            }
+            let spare = buf.capacity() - buf.len();
+            let n = unsafe {
+                libc::syscall(
+                    libc::SYS_getdents64,
+                    self.fd,
+                    buf.as_mut_ptr().add(buf.len()),
+                    spare,
+                )
+            };
+            match n {
+                0 => {
+                    return Ok((
+                        Contents {
+                            buf,
+                            dirfd: self.fd,
+                        },
+                        true,
+                    ))
+                }
+                n if n < 0 => return Err(last_error()),
+                n => unsafe { buf.set_len(buf.len() + n as usize) },
+            }
+        }
+    }
+}
+
+impl Drop for Directory {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// The raw records returned by getdents64 for a whole directory
+pub struct Contents {
+    buf: Vec<u8>,
+    /// The directory the records came from, which has to stay open while they are in use
+    dirfd: libc::c_int,
+}
+
+impl Contents {
+    /// Adds the records of `rest`, which was read from the same directory after these
+    pub fn append(&mut self, rest: Contents) {
+        self.buf.extend_from_slice(&rest.buf);
+    }
+
+    pub fn iter(&self) -> Entries<'_> {
+        Entries {
+            buf: &self.buf,
+            dirfd: self.dirfd,
+        }
+    }
+}
+
+pub struct Entries<'a> {
+    buf: &'a [u8],
+    dirfd: libc::c_int,
+}
+
+#[derive(Clone)]
+pub struct Entry<'a> {
+    name: CStr<'a>,
+    d_type: DType,
+    inode: u64,
+    dirfd: libc::c_int,
+    metadata: LazyMetadata,
+}
+
+impl<'a> Entry<'a> {
+    pub fn name(&self) -> CStr<'a> {
+        self.name
+    }
+
+    pub fn d_type(&self) -> DType {
+        self.d_type
+    }
+
+    pub fn inode(&self) -> u64 {
+        self.inode
+    }
+
+    /// What lstat says about the entry itself, which is looked up the first time it is asked for
+    pub fn metadata(&self) -> Option<Metadata> {
+        self.metadata.get(self.dirfd, self.name, false)
+    }
+}
+
+/// The parts of a stat that listings without long mode can need
+#[derive(Clone, Copy)]
+pub struct Metadata {
+    pub mode: libc::mode_t,
+    pub inode: u64,
+    pub blocks: u64,
+}
+
+/// Metadata that is looked up at most once, and only if something asks for it
+#[derive(Clone, Default)]
+pub struct LazyMetadata(Cell<Option<Option<Metadata>>>);
+
+impl LazyMetadata {
+    pub fn get(&self, dirfd: libc::c_int, name: CStr, follow: bool) -> Option<Metadata> {
+        if let Some(cached) = self.0.get() {
+            return cached;
+        }
+        let metadata = if follow {
+            syscalls::fstatat(dirfd, name)
+        } else {
+            syscalls::lstatat(dirfd, name)
+        }
+        .ok()
+        .map(|status| Metadata {
+            mode: status.st_mode,
+            inode: status.st_ino,
+            blocks: status.st_blocks as u64,
+        });
+        self.0.set(Some(metadata));
+        metadata
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        // struct linux_dirent64 { u64 d_ino; i64 d_off; u16 d_reclen; u8 d_type; char d_name[]; }
+        const NAME: usize = 19;
+        let buf = self.buf;
+        if buf.len() < NAME {
+            return None;
+        }
+        let mut inode = [0u8; 8];
+        inode.copy_from_slice(&buf[..8]);
+        let reclen = u16::from_ne_bytes([buf[16], buf[17]]) as usize;
+        let record = buf.get(..reclen).filter(|r| r.len() > NAME)?;
+        let name_len = record[NAME..].iter().position(|b| *b == 0)?;
+        self.buf = &buf[reclen..];
+        Some(Entry {
+            name: CStr::from_bytes(&record[NAME..NAME + name_len + 1]),
+            d_type: DType::from_raw(record[18]),
+            inode: u64::from_ne_bytes(inode),
+            dirfd: self.dirfd,
+            metadata: LazyMetadata::default(),
+        })
+    }
+}
@@ -0,0 +1,1028 @@
+use crate::fs::{self, DType};
+use crate::{ignore, inflate, Status};
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use veneer::{syscalls, CStr};
+
+type Oid = [u8; 20];
+/// The contents of an object that can be shared without copying
+type Object = Rc<[u8]>;
+
+const TREE_MODE: u32 = 0o040000;
+const GITLINK_MODE: u32 = 0o160000;
+
+/// Where a directory is in the work tree that contains it
+pub struct Location {
+    /// The absolute path of the directory, with symlinks resolved
+    pub dir: Vec<u8>,
+    /// The length of the prefix of `dir` that is the root of the work tree, where 0 means `/`
+    pub root_len: usize,
+    pub git_dir: Vec<u8>,
+    pub common_dir: Vec<u8>,
+}
+
+/// Finds the work tree containing the directory `path`. Both the git column and the ignore files
+/// start from here, so they always agree on which repository a directory belongs to.
+pub fn locate(path: CStr) -> Option<Location> {
+    let dir = fs::realpath(path)?;
+    let root_len = find_root(&dir)?;
+    let (git_dir, common_dir) = git_dirs(&dir[..root_len]);
+    Some(Location {
+        dir,
+        root_len,
+        git_dir,
+        common_dir,
+    })
+}
+
+/// Finds the work tree containing the absolute path `dir` by looking for `.git` in each of its
+/// ancestors. Returns the length of the prefix of `dir` that is the root, where 0 means `/`.
+fn find_root(dir: &[u8]) -> Option<usize> {
+    let mut root_len = dir.len();
+    while !fs::exists(&[&dir[..root_len], b"/.git"].concat()) {
+        if root_len == 0 {
+            return None;
+        }
+        root_len = dir[..root_len]
+            .iter()
+            .rposition(|b| *b == b'/')
+            .unwrap_or(0);
+    }
+    Some(root_len)
+}
+
+/// The git directory of the work tree at `root` and the common directory that holds its objects
+/// and refs. They differ for linked work trees, where `.git` is a file pointing elsewhere.
+fn git_dirs(root: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let dot_git = [root, b"/.git"].concat();
+    let git_dir = match fs::read(&dot_git) {
+        Some(contents) if contents.starts_with(b"gitdir:") => {
+            relative_to(root, trim(&contents[7..]))
+        }
+        _ => dot_git,
+    };
+    let common_dir = match fs::read(&[&git_dir[..], b"/commondir"].concat()) {
+        Some(contents) => relative_to(&git_dir, trim(&contents)),
+        None => git_dir.clone(),
+    };
+    (git_dir, common_dir)
+}
+
+pub enum Branch {
+    Named(Vec<u8>),
+    /// The abbreviated id of the commit HEAD points at
+    Detached(Vec<u8>),
+}
+
+/// What is checked out in a work tree and whether an operation is in progress
+pub struct Head {
+    pub branch: Branch,
+    pub operation: Option<&'static [u8]>,
+}
+
+impl Head {
+    /// Reads the state of the work tree at `root` from the files in its git directory
+    pub fn read(root: &[u8]) -> Option<Self> {
+        let (git_dir, _) = git_dirs(root);
+        let in_git_dir = |name: &[u8]| [&git_dir[..], name].concat();
+        let head = fs::read(&in_git_dir(b"/HEAD"))?;
+        let head = trim(&head);
+
+        let rebase = [&b"/rebase-merge"[..], &b"/rebase-apply"[..]]
+            .iter()
+            .map(|dir| in_git_dir(dir))
+            .find(|dir| fs::exists(dir));
+        let operation: Option<&'static [u8]> = if rebase.is_some() {
+            Some(b"rebasing")
+        } else if fs::exists(&in_git_dir(b"/MERGE_HEAD")) {
+            Some(b"merging")
+        } else if fs::exists(&in_git_dir(b"/CHERRY_PICK_HEAD")) {
+            Some(b"cherry-picking")
+        } else if fs::exists(&in_git_dir(b"/REVERT_HEAD")) {
+            Some(b"reverting")
+        } else if fs::exists(&in_git_dir(b"/BISECT_LOG")) {
+            Some(b"bisecting")
+        } else {
+            None
+        };
+
+        // HEAD is detached during a rebase, but the branch being rebased is recorded
+        let symbolic = match rebase.and_then(|dir| fs::read(&[&dir[..], b"/head-name"].concat())) {
+            Some(name) => Some(trim(&name).to_vec()),
+            None if head.starts_with(b"ref:") => Some(trim(&head[4..]).to_vec()),
+            None => None,
+        };
+
+        let branch = match symbolic {
+            Some(name) if name.starts_with(b"refs/heads/") => Branch::Named(name[11..].to_vec()),
+            Some(name) => Branch::Named(name),
+            None => Branch::Detached(head.get(..7)?.to_vec()),
+        };
+
+        Some(Head { branch, operation })
+    }
+}
+
+fn relative_to(dir: &[u8], path: &[u8]) -> Vec<u8> {
+    if path.first() == Some(&b'/') {
+        path.to_vec()
+    } else {
+        [dir, b"/", path].concat()
+    }
+}
+
+pub fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |e| e + 1);
+    &bytes[start..end]
+}
+
+/// How an entry differs from the index or from HEAD, in increasing order of importance so that
+/// directories can show the most important change among their contents
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Unchanged,
+    Ignored,
+    New,
+    Modified,
+    Conflicted,
+}
+
+/// The two letters of the git column: changes staged in the index, then changes in the work tree
+#[derive(Clone, Copy)]
+pub struct GitStatus {
+    pub staged: Change,
+    pub unstaged: Change,
+}
+
+impl GitStatus {
+    const UNCHANGED: GitStatus = GitStatus {
+        staged: Change::Unchanged,
+        unstaged: Change::Unchanged,
+    };
+
+    const CONFLICTED: GitStatus = GitStatus {
+        staged: Change::Conflicted,
+        unstaged: Change::Conflicted,
+    };
+
+    fn untracked(ignored: bool) -> Self {
+        GitStatus {
+            staged: Change::Unchanged,
+            unstaged: if ignored {
+                Change::Ignored
+            } else {
+                Change::New
+            },
+        }
+    }
+}
+
+struct IndexEntry {
+    path: Vec<u8>,
+    oid: Oid,
+    mode: u32,
+    mtime: (u32, u32),
+    size: u32,
+    stage: u8,
+}
+
+struct TreeEntry {
+    name: Vec<u8>,
+    oid: Oid,
+    mode: u32,
+}
+
+struct Pack {
+    idx: fs::Mapping,
+    data: fs::Mapping,
+}
+
+pub struct Repo {
+    /// Absolute path of the root of the work tree, empty if it is `/`
+    root: Vec<u8>,
+    git_dir: Vec<u8>,
+    common_dir: Vec<u8>,
+    /// Path of the directory being listed relative to the root, ending in '/' unless it is the
+    /// root itself
+    prefix: Vec<u8>,
+    index: Vec<IndexEntry>,
+    /// Whether each index entry differs from the work tree, filled in as directories need them
+    worktree: Vec<Option<Change>>,
+    /// The tree of the commit HEAD points at, or `None` on an unborn branch, resolved the first
+    /// time it is needed
+    head: Option<Option<Oid>>,
+    /// Trees of HEAD by their path relative to the root, in the same form as `prefix`, each
+    /// sorted by name and read the first time something in that directory is looked up
+    trees: BTreeMap<Vec<u8>, Vec<TreeEntry>>,
+    packs: Option<Vec<Pack>>,
+    /// Objects that deltas were applied to, by pack and offset, since the objects of a delta
+    /// chain are usually read one after another
+    delta_bases: BTreeMap<(usize, usize), (Kind, Object)>,
+    delta_bases_size: usize,
+}
+
+/// How many bytes of delta bases are kept before starting again with an empty cache
+const DELTA_BASES_LIMIT: usize = 16 << 20;
+
+impl Repo {
+    /// Opens the repository whose work tree contains the directory `path`
+    pub fn new(path: CStr) -> Option<Self> {
+        let Location {
+            dir,
+            root_len,
+            git_dir,
+            common_dir,
+        } = locate(path)?;
+        let root = dir[..root_len].to_vec();
+
+        let index = match fs::Mapping::open(&[&git_dir[..], b"/index"].concat()) {
+            Some(contents) => parse_index(&contents)?,
+            None => Vec::new(),
+        };
+
+        let mut prefix = Vec::new();
+        for component in dir[root_len..].split(|b| *b == b'/') {
+            if !component.is_empty() {
+                prefix.extend_from_slice(component);
+                prefix.push(b'/');
+            }
+        }
+
+        Some(Repo {
+            root,
+            git_dir,
+            common_dir,
+            prefix,
+            worktree: index.iter().map(|_| None).collect(),
+            index,
+            head: None,
+            trees: BTreeMap::new(),
+            packs: None,
+            delta_bases: BTreeMap::new(),
+            delta_bases_size: 0,
+        })
+    }
+
+    /// Descends into the subdirectory `name`, returning the mark to pass to `leave`
+    pub fn enter(&mut self, name: &[u8]) -> usize {
+        let mark = self.prefix.len();
+        self.prefix.extend_from_slice(name);
+        self.prefix.push(b'/');
+        mark
+    }
+
+    pub fn leave(&mut self, mark: usize) {
+        self.prefix.truncate(mark);
+    }
+
+    /// Status of the entry `name` in the current directory, which may also be a path relative to
+    /// it as given on the command line. `ignore` holds the rules of the current directory.
+    pub fn status(
+        &mut self,
+        name: &[u8],
+        status: &Status,
+        ignore: Option<&mut ignore::Ignore>,
+    ) -> GitStatus {
+        let path = match self.repo_path(name) {
+            Some(path) => path,
+            None => return GitStatus::UNCHANGED,
+        };
+        let slash = path.iter().rposition(|b| *b == b'/');
+        let base = &path[slash.map_or(0, |p| p + 1)..];
+
+        // A path from the command line can be anywhere in the work tree, so it gets the rules of
+        // the directory it is in
+        let mut own_ignore;
+        let ignore = if name.contains(&b'/') {
+            let parent = [&self.root[..], b"/", &path[..slash.unwrap_or(0)]].concat();
+            own_ignore = ignore::Ignore::new(CStr::from_bytes(&fs::nul_terminated(&parent)));
+            own_ignore.as_mut()
+        } else {
+            ignore
+        };
+
+        let is_dir = status.mode & libc::S_IFMT == libc::S_IFDIR;
+        let ignored = match &ignore {
+            Some(ignore) => ignore.is_ignored(base, is_dir),
+            None => false,
+        };
+        if is_dir {
+            self.directory_status(&path, ignored, ignore)
+        } else {
+            self.file_status(&path, status, ignored)
+        }
+    }
+
+    fn file_status(&mut self, path: &[u8], status: &Status, ignored: bool) -> GitStatus {
+        let start = self.lower_bound(path);
+        let end = start
+            + self.index[start..]
+                .iter()
+                .take_while(|e| e.path == path)
+                .count();
+        if start == end {
+            return GitStatus::untracked(ignored);
+        }
+        if self.index[start..end].iter().any(|e| e.stage != 0) {
+            return GitStatus::CONFLICTED;
+        }
+
+        let entry = &self.index[start];
+        let changed = differs(
+            entry,
+            status.mode,
+            status.size,
+            (status.mtime, status.mtime_nsec),
+        );
+        GitStatus {
+            staged: self.staged_change(start),
+            unstaged: if changed {
+                Change::Modified
+            } else {
+                Change::Unchanged
+            },
+        }
+    }
+
+    /// The most important change among everything the index or HEAD has under `path`
+    fn directory_status(
+        &mut self,
+        path: &[u8],
+        ignored: bool,
+        ignore: Option<&mut ignore::Ignore>,
+    ) -> GitStatus {
+        // Submodules are recorded as a single entry for the directory
+        let start = self.lower_bound(path);
+        if let Some(entry) = self.index.get(start) {
+            if entry.path == path {
+                return GitStatus {
+                    staged: self.staged_change(start),
+                    unstaged: Change::Unchanged,
+                };
+            }
+        }
+
+        let mut dir = path.to_vec();
+        dir.push(b'/');
+        let start = self.lower_bound(&dir);
+        let end = start
+            + self.index[start..]
+                .iter()
+                .take_while(|e| e.path.starts_with(&dir))
+                .count();
+        if start == end {
+            return GitStatus::untracked(ignored);
+        }
+
+        let mut result = GitStatus::UNCHANGED;
+        for i in start..end {
+            if self.index[i].stage != 0 {
+                return GitStatus::CONFLICTED;
+            }
+            result.staged = result.staged.max(self.staged_change(i));
+            result.unstaged = result.unstaged.max(self.worktree_change(i));
+        }
+
+        // Files that were removed from the index are still in HEAD
+        if result.staged < Change::Modified && self.removed_from_index(&mut dir) {
+            result.staged = Change::Modified;
+        }
+        // Untracked files count too, unless they are ignored
+        if result.unstaged < Change::New && self.has_untracked(&mut dir, ignore) {
+            result.unstaged = Change::New;
+        }
+
+        result
+    }
+
+    fn staged_change(&mut self, i: usize) -> Change {
+        let (oid, mode) = (self.index[i].oid, self.index[i].mode);
+        let path = self.index[i].path.clone();
+        let (dir, name) = path.split_at(path.iter().rposition(|b| *b == b'/').map_or(0, |p| p + 1));
+        let tree = self.head_tree(dir).unwrap_or(&[]);
+        match tree.binary_search_by(|e| e.name.as_slice().cmp(name)) {
+            Ok(h) if tree[h].oid == oid && tree[h].mode == mode => Change::Unchanged,
+            Ok(h) if tree[h].mode != TREE_MODE => Change::Modified,
+            // A file where HEAD has a directory is as new as one where it has nothing
+            _ => Change::New,
+        }
+    }
+
+    /// Whether anything HEAD has under the directory `dir`, a path relative to the root ending in
+    /// '/', is missing from the index
+    fn removed_from_index(&mut self, dir: &mut Vec<u8>) -> bool {
+        let entries: Vec<(Vec<u8>, bool)> = match self.head_tree(dir) {
+            Some(tree) => tree
+                .iter()
+                .map(|e| (e.name.clone(), e.mode == TREE_MODE))
+                .collect(),
+            None => return false,
+        };
+        for (name, is_tree) in entries {
+            let len = dir.len();
+            dir.extend_from_slice(&name);
+            let removed = if is_tree {
+                dir.push(b'/');
+                self.removed_from_index(dir)
+            } else {
+                !self.in_index(dir)
+            };
+            dir.truncate(len);
+            if removed {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether the directory `dir`, a path relative to the root ending in '/', holds a file that
+    /// is neither in the index nor ignored, the way `git status` finds untracked files. `ignore`
+    /// holds the rules of the directory that contains it.
+    fn has_untracked(&self, dir: &mut Vec<u8>, ignore: Option<&mut ignore::Ignore>) -> bool {
+        let path = fs::nul_terminated(&[&self.root[..], b"/", dir].concat());
+        let directory = match fs::Directory::open(CStr::from_bytes(&path)) {
+            Ok(directory) => directory,
+            Err(_) => return false,
+        };
+        match ignore {
+            Some(ignore) => {
+                let name = &dir[..dir.len() - 1];
+                let name = &name[name.iter().rposition(|b| *b == b'/').map_or(0, |p| p + 1)..];
+                let mark = ignore.enter(name, directory.raw_fd());
+                let found = self.untracked_in(dir, &directory, Some(ignore));
+                ignore.leave(mark);
+                found
+            }
+            None => self.untracked_in(dir, &directory, None),
+        }
+    }
+
+    fn untracked_in(
+        &self,
+        dir: &mut Vec<u8>,
+        directory: &fs::Directory,
+        mut ignore: Option<&mut ignore::Ignore>,
+    ) -> bool {
+        let contents = match directory.read() {
+            Ok(contents) => contents,
+            Err(_) => return false,
+        };
+        for e in contents.iter() {
+            let name = e.name().as_bytes();
+            if name == b"." || name == b".." || name == b".git" {
+                continue;
+            }
+            let is_dir = match e.d_type() {
+                DType::DIR => true,
+                DType::UNKNOWN => syscalls::lstatat(directory.raw_fd(), e.name())
+                    .map(|s| s.st_mode & libc::S_IFMT == libc::S_IFDIR)
+                    .unwrap_or(false),
+                _ => false,
+            };
+            if let Some(ignore) = &ignore {
+                if ignore.is_ignored(name, is_dir) {
+                    continue;
+                }
+            }
+
+            let len = dir.len();
+            dir.extend_from_slice(name);
+            let found = if !is_dir {
+                !self.in_index(dir)
+            } else if self.in_index(dir) {
+                // Submodules are a single entry in the index, and their contents are their own
+                false
+            } else {
+                dir.push(b'/');
+                match fs::Directory::open_at(directory.raw_fd(), e.name(), false) {
+                    Ok(subdirectory) => {
+                        let mark = ignore
+                            .as_mut()
+                            .map(|ignore| ignore.enter(name, subdirectory.raw_fd()));
+                        let found = self.untracked_in(dir, &subdirectory, ignore.as_deref_mut());
+                        if let (Some(ignore), Some(mark)) = (ignore.as_mut(), mark) {
+                            ignore.leave(mark);
+                        }
+                        found
+                    }
+                    Err(_) => false,
+                }
+            };
+            dir.truncate(len);
+            if found {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn in_index(&self, path: &[u8]) -> bool {
+        matches!(self.index.get(self.lower_bound(path)), Some(e) if e.path == path)
+    }
+
+    fn worktree_change(&mut self, i: usize) -> Change {
+        if let Some(change) = self.worktree[i] {
+            return change;
+        }
+        let entry = &self.index[i];
+        let path = [&self.root[..], b"/", &entry.path, b"\0"].concat();
+        let change = match syscalls::lstatat(libc::AT_FDCWD, CStr::from_bytes(&path)) {
+            Ok(s) if !differs(entry, s.st_mode, s.st_size, (s.st_mtime, s.st_mtime_nsec)) => {
+                Change::Unchanged
+            }
+            // Deleted files count as modified too
+            _ => Change::Modified,
+        };
+        self.worktree[i] = Some(change);
+        change
+    }
+
+    /// Index of the first entry whose path is not less than `path`
+    fn lower_bound(&self, path: &[u8]) -> usize {
+        self.index
+            .binary_search_by(|e| {
+                e.path
+                    .as_slice()
+                    .cmp(path)
+                    .then(core::cmp::Ordering::Greater)
+            })
+            .unwrap_or_else(|i| i)
+    }
+
+    /// Resolves `name` against the current directory into a path relative to the root
+    fn repo_path(&self, name: &[u8]) -> Option<Vec<u8>> {
+        let (mut path, name) = if name.first() == Some(&b'/') {
+            let inside = name.get(self.root.len()..)?;
+            if !name.starts_with(&self.root) || inside.first() != Some(&b'/') {
+                return None;
+            }
+            (Vec::new(), inside)
+        } else {
+            (self.prefix.clone(), name)
+        };
+
+        for component in name.split(|b| *b == b'/') {
+            match component {
+                b"" | b"." => {}
+                b".." => {
+                    // Popping the separator fails if this would leave the work tree
+                    path.pop()?;
+                    let parent = path.iter().rposition(|b| *b == b'/').map_or(0, |p| p + 1);
+                    path.truncate(parent);
+                }
+                _ => {
+                    path.extend_from_slice(component);
+                    path.push(b'/');
+                }
+            }
+        }
+        // The root itself has no status of its own, and neither does anything in .git
+        path.pop()?;
+        if path == b".git" || path.starts_with(b".git/") {
+            return None;
+        }
+        Some(path)
+    }
+
+    /// The tree of the commit HEAD points at
+    fn head_root(&mut self) -> Option<Oid> {
+        if self.head.is_none() {
+            let tree = self
+                .resolve_head()
+                .and_then(|commit| match self.read_object(&commit) {
+                    Some((Kind::Commit, body)) if body.starts_with(b"tree ") => {
+                        body.get(5..45).and_then(parse_oid)
+                    }
+                    _ => None,
+                });
+            self.head = Some(tree);
+        }
+        self.head?
+    }
+
+    /// The entries HEAD has in the directory `dir`, a path relative to the root in the same form
+    /// as `prefix`, or `None` if it has no such directory
+    fn head_tree(&mut self, dir: &[u8]) -> Option<&[TreeEntry]> {
+        if !self.trees.contains_key(dir) {
+            let oid = match dir.split_last() {
+                None => self.head_root()?,
+                // The parent's entry for the directory names its tree
+                Some((_, dir)) => {
+                    let split = dir.iter().rposition(|b| *b == b'/').map_or(0, |p| p + 1);
+                    let (parent, name) = dir.split_at(split);
+                    let parent = self.head_tree(parent)?;
+                    let i = parent
+                        .binary_search_by(|e| e.name.as_slice().cmp(name))
+                        .ok()?;
+                    if parent[i].mode != TREE_MODE {
+                        return None;
+                    }
+                    parent[i].oid
+                }
+            };
+            let tree = match self.read_object(&oid) {
+                Some((Kind::Tree, tree)) => parse_tree(&tree),
+                _ => Vec::new(),
+            };
+            self.trees.insert(dir.to_vec(), tree);
+        }
+        self.trees.get(dir).map(Vec::as_slice)
+    }
+
+    /// The commit HEAD points at, or `None` on an unborn branch
+    fn resolve_head(&self) -> Option<Oid> {
+        let head = fs::read(&[&self.git_dir[..], b"/HEAD"].concat())?;
+        let mut target = trim(&head).to_vec();
+        // Symbolic refs can point at other symbolic refs, but not forever
+        for _ in 0..5 {
+            if !target.starts_with(b"ref:") {
+                return parse_oid(&target);
+            }
+            let name = trim(&target[4..]).to_vec();
+            target = match fs::read(&[&self.common_dir[..], b"/", &name].concat()) {
+                Some(contents) => trim(&contents).to_vec(),
+                None => return self.packed_ref(&name),
+            };
+        }
+        None
+    }
+
+    fn packed_ref(&self, name: &[u8]) -> Option<Oid> {
+        let packed = fs::read(&[&self.common_dir[..], b"/packed-refs"].concat())?;
+        packed
+            .split(|b| *b == b'\n')
+            .filter(|line| line.len() > 41 && line[40] == b' ')
+            .find(|line| trim(&line[41..]) == name)
+            .and_then(|line| parse_oid(&line[..40]))
+    }
+
+    fn read_object(&mut self, oid: &Oid) -> Option<(Kind, Vec<u8>)> {
+        let mut path = self.common_dir.clone();
+        path.extend_from_slice(b"/objects/");
+        for (i, byte) in oid.iter().enumerate() {
+            path.push(HEX[(byte >> 4) as usize]);
+            path.push(HEX[(byte & 0xf) as usize]);
+            if i == 0 {
+                path.push(b'/');
+            }
+        }
+
+        if let Some(object) = fs::read(&path).and_then(|c| inflate::zlib(&c)) {
+            let nul = object.iter().position(|b| *b == 0)?;
+            let kind = match object.split(|b| *b == b' ').next()? {
+                b"commit" => Kind::Commit,
+                b"tree" => Kind::Tree,
+                b"blob" => Kind::Blob,
+                b"tag" => Kind::Tag,
+                _ => return None,
+            };
+            return Some((kind, object[nul + 1..].to_vec()));
+        }
+
+        self.load_packs();
+        let packs = self.packs.as_deref().unwrap_or(&[]);
+        let (pack, offset) = packs
+            .iter()
+            .enumerate()
+            .find_map(|(i, pack)| pack.find(oid).map(|offset| (i, offset)))?;
+        self.read_packed(pack, offset)
+    }
+
+    /// Reads the object at `offset` in the `pack`th pack
+    fn read_packed(&mut self, pack: usize, offset: usize) -> Option<(Kind, Vec<u8>)> {
+        let (base, delta) = {
+            let pack = &self.packs.as_deref()?[pack];
+            let data = pack.data.get(offset..)?;
+            let mut i = 0;
+            let mut byte = *data.first()?;
+            let kind = (byte >> 4) & 7;
+            while byte & 0x80 != 0 {
+                i += 1;
+                byte = *data.get(i)?;
+            }
+            i += 1;
+
+            match kind {
+                1 => return Some((Kind::Commit, inflate::zlib(&data[i..])?)),
+                2 => return Some((Kind::Tree, inflate::zlib(&data[i..])?)),
+                3 => return Some((Kind::Blob, inflate::zlib(&data[i..])?)),
+                4 => return Some((Kind::Tag, inflate::zlib(&data[i..])?)),
+                // Deltas against an object earlier in the same pack
+                6 => {
+                    let (distance, len) = offset_varint(&data[i..])?;
+                    let delta = inflate::zlib(data.get(i + len..)?)?;
+                    (offset.checked_sub(distance)?, delta)
+                }
+                // Deltas against an object named by its id, which is always in the same pack in
+                // practice since we do not support thin packs
+                7 => {
+                    let base_oid = data.get(i..i + 20).and_then(to_oid)?;
+                    let delta = inflate::zlib(data.get(i + 20..)?)?;
+                    (pack.find(&base_oid)?, delta)
+                }
+                _ => return None,
+            }
+        };
+        let (kind, base) = self.delta_base(pack, base)?;
+        Some((kind, apply_delta(&base, &delta)?))
+    }
+
+    /// The object at `offset` in the `pack`th pack, from the cache if a delta needed it before
+    fn delta_base(&mut self, pack: usize, offset: usize) -> Option<(Kind, Object)> {
+        if let Some((kind, base)) = self.delta_bases.get(&(pack, offset)) {
+            return Some((*kind, base.clone()));
+        }
+        let (kind, base) = self.read_packed(pack, offset)?;
+        let base: Object = base.into();
+        if self.delta_bases_size + base.len() > DELTA_BASES_LIMIT {
+            self.delta_bases.clear();
+            self.delta_bases_size = 0;
+        }
+        self.delta_bases_size += base.len();
+        self.delta_bases
+            .insert((pack, offset), (kind, base.clone()));
+        Some((kind, base))
+    }
+
+    fn load_packs(&mut self) {
+        if self.packs.is_some() {
+            return;
+        }
+        let mut packs = Vec::new();
+        let pack_dir = [&self.common_dir[..], b"/objects/pack"].concat();
+        if let Ok(dir) = veneer::Directory::open(CStr::from_bytes(&fs::nul_terminated(&pack_dir))) {
+            if let Ok(contents) = dir.read() {
+                for e in contents.iter() {
+                    let name = e.name().as_bytes();
+                    if !name.ends_with(b".idx") {
+                        continue;
+                    }
+                    let idx_path = [&pack_dir[..], b"/", name].concat();
+                    let pack_path = [&idx_path[..idx_path.len() - 4], b".pack"].concat();
+                    if let (Some(idx), Some(data)) =
+                        (fs::Mapping::open(&idx_path), fs::Mapping::open(&pack_path))
+                    {
+                        packs.push(Pack { idx, data });
+                    }
+                }
+            }
+        }
+        self.packs = Some(packs);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl Pack {
+    /// Offset of the object `oid` in a version 2 pack index
+    fn find(&self, oid: &Oid) -> Option<usize> {
+        let idx = &self.idx[..];
+        if idx.get(..4)? != b"\xfftOc" || be32(idx, 4)? != 2 {
+            return None;
+        }
+        let fanout = 8;
+        let count = be32(idx, fanout + 255 * 4)? as usize;
+        let mut low = match oid[0] {
+            0 => 0,
+            first => be32(idx, fanout + (first as usize - 1) * 4)? as usize,
+        };
+        let mut high = be32(idx, fanout + oid[0] as usize * 4)? as usize;
+
+        let names = fanout + 256 * 4;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let name = idx.get(names + mid * 20..names + mid * 20 + 20)?;
+            match name.cmp(&oid[..]) {
+                core::cmp::Ordering::Less => low = mid + 1,
+                core::cmp::Ordering::Greater => high = mid,
+                core::cmp::Ordering::Equal => {
+                    let offsets = names + count * 20 + count * 4;
+                    let offset = be32(idx, offsets + mid * 4)?;
+                    // Offsets past 2 GiB are stored in a separate table of 64-bit values
+                    return if offset & 0x8000_0000 == 0 {
+                        Some(offset as usize)
+                    } else {
+                        let large = offsets + count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+                        let bytes = idx.get(large..large + 8)?;
+                        let mut value = [0u8; 8];
+                        value.copy_from_slice(bytes);
+                        Some(u64::from_be_bytes(value) as usize)
+                    };
+                }
+            }
+        }
+        None
+    }
+}
+
+fn differs(entry: &IndexEntry, mode: libc::mode_t, size: libc::off_t, mtime: (i64, i64)) -> bool {
+    if entry.mode == GITLINK_MODE {
+        return false;
+    }
+    // Some builds of git do not record nanoseconds, so only compare them if they are there
+    let nsec_differs = entry.mtime.1 != 0 && entry.mtime.1 as i64 != mtime.1;
+    entry.mtime.0 as i64 != mtime.0
+        || nsec_differs
+        || entry.size != size as u32
+        || entry.mode & libc::S_IFMT != mode & libc::S_IFMT
+        || entry.mode & 0o100 != mode & 0o100
+}
+
+/// Parses versions 2 to 4 of the index file format
+fn parse_index(data: &[u8]) -> Option<Vec<IndexEntry>> {
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be32(data, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = be32(data, 8)? as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    let mut path = Vec::new();
+    let mut pos = 12;
+    for _ in 0..count {
+        let fixed = data.get(pos..pos + 62)?;
+        let flags = u16::from_be_bytes([fixed[60], fixed[61]]);
+        let mut name_start = pos + 62;
+        if version >= 3 && flags & 0x4000 != 0 {
+            name_start += 2;
+        }
+
+        if version == 4 {
+            // Paths are stored as the number of bytes to remove from the end of the previous
+            // path followed by the bytes to append, with no padding
+            let (strip, len) = offset_varint(data.get(name_start..)?)?;
+            path.truncate(path.len().checked_sub(strip)?);
+            name_start += len;
+            let name_len = data.get(name_start..)?.iter().position(|b| *b == 0)?;
+            path.extend_from_slice(&data[name_start..name_start + name_len]);
+            pos = name_start + name_len + 1;
+        } else {
+            // Entries are padded with 1 to 8 nul bytes to a multiple of 8 bytes
+            let name_len = data.get(name_start..)?.iter().position(|b| *b == 0)?;
+            path.clear();
+            path.extend_from_slice(&data[name_start..name_start + name_len]);
+            pos += (name_start - pos + name_len + 8) & !7;
+        }
+
+        entries.push(IndexEntry {
+            path: path.clone(),
+            oid: to_oid(&fixed[40..60])?,
+            mode: be32(fixed, 24)?,
+            mtime: (be32(fixed, 8)?, be32(fixed, 12)?),
+            size: be32(fixed, 36)?,
+            stage: ((flags >> 12) & 3) as u8,
+        });
+    }
+    Some(entries)
+}
+
+/// The entries of a tree object, sorted by name
+fn parse_tree(tree: &[u8]) -> Vec<TreeEntry> {
+    let mut entries = Vec::new();
+    let mut rest = tree;
+    while let Some(space) = rest.iter().position(|b| *b == b' ') {
+        let nul = match rest.iter().position(|b| *b == 0) {
+            Some(nul) if nul > space => nul,
+            _ => break,
+        };
+        let oid = match rest.get(nul + 1..nul + 21).and_then(to_oid) {
+            Some(oid) => oid,
+            None => break,
+        };
+        let mode = rest[..space].iter().fold(0, |mode, digit| {
+            mode * 8 + (digit.wrapping_sub(b'0') as u32 & 7)
+        });
+        entries.push(TreeEntry {
+            name: rest[space + 1..nul].to_vec(),
+            oid,
+            mode,
+        });
+        rest = &rest[nul + 21..];
+    }
+    // Git sorts directories as if their names ended in '/', which is not the order lookups use
+    entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let (base_len, mut i) = size_varint(delta)?;
+    if base_len != base.len() {
+        return None;
+    }
+    let (result_len, len) = size_varint(&delta[i..])?;
+    i += len;
+
+    let mut result = Vec::with_capacity(result_len);
+    while let Some(&op) = delta.get(i) {
+        i += 1;
+        if op & 0x80 != 0 {
+            // Copy a range of the base, with the offset and size given by whichever of the
+            // following bytes the low bits of op say are present
+            let mut offset = 0;
+            for byte in 0..4 {
+                if op & (1 << byte) != 0 {
+                    offset |= (*delta.get(i)? as usize) << (8 * byte);
+                    i += 1;
+                }
+            }
+            let mut size = 0;
+            for byte in 0..3 {
+                if op & (0x10 << byte) != 0 {
+                    size |= (*delta.get(i)? as usize) << (8 * byte);
+                    i += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            result.extend_from_slice(base.get(offset..offset + size)?);
+        } else if op != 0 {
+            result.extend_from_slice(delta.get(i..i + op as usize)?);
+            i += op as usize;
+        } else {
+            return None;
+        }
+    }
+
+    if result.len() == result_len {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+/// The little-endian base 128 encoding used for sizes in deltas
+fn size_varint(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// The big-endian encoding used for delta offsets and index v4 paths, where each continuation
+/// also adds one so that every value has exactly one representation
+fn offset_varint(data: &[u8]) -> Option<(usize, usize)> {
+    let mut i = 0;
+    let mut byte = *data.first()?;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        i += 1;
+        byte = *data.get(i)?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+    Some((value, i + 1))
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+fn to_oid(bytes: &[u8]) -> Option<Oid> {
+    if bytes.len() != 20 {
+        return None;
+    }
+    let mut oid = [0u8; 20];
+    oid.copy_from_slice(bytes);
+    Some(oid)
+}
+
+pub fn parse_oid(hex: &[u8]) -> Option<Oid> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let digit = |c: u8| match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    };
+    let mut oid = [0u8; 20];
+    for (i, pair) in hex.chunks(2).enumerate() {
+        oid[i] = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Some(oid)
+}
@@ -0,0 +1,133 @@
+use crate::fs;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use veneer::CStr;
+
+/// Names by user or group id, or `None` for ids that have none
+type IdMap = BTreeMap<u32, Option<Vec<u8>>>;
+
+/// Turns user and group ids into names. /etc/passwd and /etc/group are read the first time a name
+/// is needed, and anything they do not have is asked of NSS, which knows about LDAP and the like.
+/// Every answer is kept for the rest of the run.
+pub struct Resolver {
+    users: IdMap,
+    groups: IdMap,
+    loaded: bool,
+    /// The user running fls and the groups it is in, once asked about
+    me: Option<(libc::uid_t, Vec<libc::gid_t>)>,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    User,
+    Group,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            users: IdMap::new(),
+            groups: IdMap::new(),
+            loaded: false,
+            me: None,
+        }
+    }
+
+    /// Whether `uid` is the user running fls
+    pub fn is_me(&mut self, uid: libc::uid_t) -> bool {
+        self.me().0 == uid
+    }
+
+    /// Whether the user running fls is in the group `gid`
+    pub fn is_my_group(&mut self, gid: libc::gid_t) -> bool {
+        self.me().1.contains(&gid)
+    }
+
+    fn me(&mut self) -> &(libc::uid_t, Vec<libc::gid_t>) {
+        self.me.get_or_insert_with(|| unsafe {
+            let mut groups = alloc::vec![libc::getegid()];
+            let count = libc::getgroups(0, core::ptr::null_mut());
+            if count > 0 {
+                let start = groups.len();
+                groups.resize(start + count as usize, 0);
+                let count = libc::getgroups(count, groups[start..].as_mut_ptr());
+                groups.truncate(start + count.max(0) as usize);
+            }
+            (libc::geteuid(), groups)
+        })
+    }
+
+    pub fn user(&mut self, uid: libc::uid_t) -> Option<&[u8]> {
+        self.lookup(Kind::User, uid)
+    }
+
+    pub fn group(&mut self, gid: libc::gid_t) -> Option<&[u8]> {
+        self.lookup(Kind::Group, gid)
+    }
+
+    fn lookup(&mut self, kind: Kind, id: u32) -> Option<&[u8]> {
+        if !self.loaded {
+            self.loaded = true;
+            if let Some(passwd) = fs::read(b"/etc/passwd") {
+                load(&mut self.users, &passwd);
+            }
+            if let Some(group) = fs::read(b"/etc/group") {
+                load(&mut self.groups, &group);
+            }
+        }
+
+        let map = match kind {
+            Kind::User => &mut self.users,
+            Kind::Group => &mut self.groups,
+        };
+        map.entry(id)
+            .or_insert_with(|| from_nss(kind, id))
+            .as_deref()
+    }
+}
+
+/// Adds the entries of a passwd or group file, whose first and third fields are the name and id
+fn load(map: &mut IdMap, contents: &[u8]) {
+    for line in contents.split(|b| *b == b'\n') {
+        let mut fields = line.split(|b| *b == b':');
+        let name = fields.next();
+        let id = fields.nth(1).and_then(parse_id);
+        if let (Some(name), Some(id)) = (name, id) {
+            // getpwuid returns the first match, so later duplicates lose
+            if !name.is_empty() {
+                map.entry(id).or_insert_with(|| Some(name.to_vec()));
+            }
+        }
+    }
+}
+
+fn parse_id(field: &[u8]) -> Option<u32> {
+    if field.is_empty() {
+        return None;
+    }
+    field.iter().try_fold(0u32, |id, b| {
+        if b.is_ascii_digit() {
+            id.checked_mul(10)?.checked_add((b - b'0') as u32)
+        } else {
+            None
+        }
+    })
+}
+
+fn from_nss(kind: Kind, id: u32) -> Option<Vec<u8>> {
+    unsafe {
+        let name = match kind {
+            Kind::User => {
+                core::ptr::NonNull::new(libc::getpwuid(id))?
+                    .as_ref()
+                    .pw_name
+            }
+            Kind::Group => {
+                core::ptr::NonNull::new(libc::getgrgid(id))?
+                    .as_ref()
+                    .gr_name
+            }
+        };
+        Some(CStr::from_ptr(name).as_bytes().to_vec())
+    }
+}
@@ -0,0 +1,360 @@
+use crate::{fs, git};
+use alloc::vec::Vec;
+use veneer::CStr;
+
+/// The gitignore rules that apply to the directory currently being listed.
+///
+/// Rules are kept in a stack ordered from lowest to highest precedence: the global excludes file,
+/// `.git/info/exclude`, then `.gitignore` and `.ignore` from the repository root down to the
+/// current directory. Entering a subdirectory pushes its rules and leaving pops them again, so
+/// the last matching rule is always the one that wins.
+pub struct Ignore {
+    rules: Vec<Rule>,
+    /// Path of the current directory relative to the repository root, ending in '/' unless it is
+    /// the root itself
+    prefix: Vec<u8>,
+    /// Set once we are inside an ignored directory; git does not allow re-including anything in
+    /// there, so every entry is ignored
+    inside_ignored: bool,
+    /// Sorted names of the ignored entries in the current directory, when they are dimmed
+    /// instead of hidden
+    dimmed: Vec<Vec<u8>>,
+}
+
+struct Rule {
+    /// Directory containing the file this rule came from, in the same form as `Ignore::prefix`
+    base: Vec<u8>,
+    pattern: Vec<u8>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+pub struct Mark {
+    rules: usize,
+    prefix: usize,
+    inside_ignored: bool,
+}
+
+impl Ignore {
+    /// Loads every ignore file that applies to the directory `path`, or returns `None` if it is
+    /// not inside a git work tree.
+    pub fn new(path: CStr) -> Option<Self> {
+        let git::Location {
+            dir,
+            root_len,
+            common_dir,
+            ..
+        } = git::locate(path)?;
+
+        let mut ignore = Ignore {
+            rules: Vec::new(),
+            prefix: Vec::new(),
+            inside_ignored: false,
+            dimmed: Vec::new(),
+        };
+
+        if let Some(contents) = global_excludes() {
+            ignore.add_rules(&contents);
+        }
+        if let Some(contents) = fs::read(&[&common_dir[..], b"/info/exclude"].concat()) {
+            ignore.add_rules(&contents);
+        }
+
+        let mut path = dir[..root_len].to_vec();
+        ignore.load_path(&path);
+        for component in dir[root_len..].split(|b| *b == b'/') {
+            if component.is_empty() {
+                continue;
+            }
+            ignore.inside_ignored |= ignore.is_ignored(component, true);
+            ignore.prefix.extend_from_slice(component);
+            ignore.prefix.push(b'/');
+            path.push(b'/');
+            path.extend_from_slice(component);
+            ignore.load_path(&path);
+        }
+
+        Some(ignore)
+    }
+
+    /// Descends into the subdirectory `name` of the current directory, which is open as `dirfd`
+    pub fn enter(&mut self, name: &[u8], dirfd: libc::c_int) -> Mark {
+        let mark = Mark {
+            rules: self.rules.len(),
+            prefix: self.prefix.len(),
+            inside_ignored: self.inside_ignored,
+        };
+        self.inside_ignored |= self.is_ignored(name, true);
+        self.prefix.extend_from_slice(name);
+        self.prefix.push(b'/');
+        self.load(dirfd);
+        mark
+    }
+
+    /// Returns to the directory we were in when `mark` was created by `enter`
+    pub fn leave(&mut self, mark: Mark) {
+        self.rules.truncate(mark.rules);
+        self.prefix.truncate(mark.prefix);
+        self.inside_ignored = mark.inside_ignored;
+    }
+
+    pub fn is_ignored(&self, name: &[u8], is_dir: bool) -> bool {
+        if self.inside_ignored {
+            return true;
+        }
+        let mut path = Vec::with_capacity(self.prefix.len() + name.len());
+        path.extend_from_slice(&self.prefix);
+        path.extend_from_slice(name);
+
+        let last_match = self
+            .rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.matches(&path, name));
+        matches!(last_match, Some(rule) if !rule.negated)
+    }
+
+    pub fn set_dimmed(&mut self, mut names: Vec<Vec<u8>>) {
+        names.sort_unstable();
+        self.dimmed = names;
+    }
+
+    pub fn is_dimmed(&self, name: &[u8]) -> bool {
+        self.dimmed
+            .binary_search_by(|n| n.as_slice().cmp(name))
+            .is_ok()
+    }
+
+    fn load_path(&mut self, dir: &[u8]) {
+        let dir = fs::nul_terminated(if dir.is_empty() { b"/" } else { dir });
+        let fd = unsafe {
+            libc::open(
+                dir.as_ptr() as *const libc::c_char,
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+        if fd >= 0 {
+            self.load(fd);
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    fn load(&mut self, dirfd: libc::c_int) {
+        // .ignore files take precedence over .gitignore in the same directory
+        for file in &[&b".gitignore\0"[..], &b".ignore\0"[..]] {
+            if let Some(contents) = fs::read_at(dirfd, file) {
+                self.add_rules(&contents);
+            }
+        }
+    }
+
+    fn add_rules(&mut self, contents: &[u8]) {
+        for line in contents.split(|b| *b == b'\n') {
+            let mut line = if line.last() == Some(&b'\r') {
+                &line[..line.len() - 1]
+            } else {
+                line
+            };
+
+            if line.first() == Some(&b'#') {
+                continue;
+            }
+
+            // Trailing spaces are ignored unless they are escaped with a backslash
+            while line.last() == Some(&b' ') && !line[..line.len() - 1].ends_with(b"\\") {
+                line = &line[..line.len() - 1];
+            }
+
+            // A leading backslash escapes a literal '!' or '#'
+            let negated = line.first() == Some(&b'!');
+            if negated || line.starts_with(b"\\!") || line.starts_with(b"\\#") {
+                line = &line[1..];
+            }
+
+            let dir_only = line.last() == Some(&b'/');
+            if dir_only {
+                line = &line[..line.len() - 1];
+            }
+
+            // A separator anywhere but the end means the pattern is relative to the directory of
+            // the file it is in, otherwise it matches a name at any depth
+            let anchored = line.contains(&b'/');
+            if line.first() == Some(&b'/') {
+                line = &line[1..];
+            }
+
+            if line.is_empty() {
+                continue;
+            }
+
+            self.rules.push(Rule {
+                base: self.prefix.clone(),
+                pattern: line.to_vec(),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+}
+
+impl Rule {
+    fn matches(&self, path: &[u8], name: &[u8]) -> bool {
+        if self.anchored {
+            // Rules are popped when we leave the directory they came from, so their base is
+            // always a prefix of the current path
+            glob(&self.pattern, &path[self.base.len()..])
+        } else {
+            glob(&self.pattern, name)
+        }
+    }
+}
+
+/// Matches `text` against a gitignore glob, where `*` and `?` never match a '/' and `**` between
+/// separators matches any number of directories
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            if rest.is_empty() {
+                true
+            } else if rest[0] == b'/' {
+                let rest = &rest[1..];
+                glob(rest, text)
+                    || text
+                        .iter()
+                        .enumerate()
+                        .any(|(i, b)| *b == b'/' && glob(rest, &text[i + 1..]))
+            } else {
+                glob(&pattern[1..], text)
+            }
+        }
+        Some(b'*') => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob(&pattern[1..], &text[i..])),
+        Some(b'?') => match text.first() {
+            Some(b'/') | None => false,
+            Some(_) => glob(&pattern[1..], &text[1..]),
+        },
+        Some(b'[') => match (text.first(), class(&pattern[1..])) {
+            (Some(&c), Some((set, len))) if c != b'/' => {
+                set.contains(c) && glob(&pattern[1 + len..], &text[1..])
+            }
+            (Some(&c), None) => c == b'[' && glob(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob(&pattern[2..], &text[1..])
+        }
+        Some(&p) => text.first() == Some(&p) && glob(&pattern[1..], &text[1..]),
+    }
+}
+
+struct Class<'a> {
+    members: &'a [u8],
+    negated: bool,
+}
+
+impl<'a> Class<'a> {
+    fn contains(&self, c: u8) -> bool {
+        let mut found = false;
+        let mut i = 0;
+        while i < self.members.len() {
+            let mut low = self.members[i];
+            if low == b'\\' && i + 1 < self.members.len() {
+                i += 1;
+                low = self.members[i];
+            }
+            if self.members.get(i + 1) == Some(&b'-') && i + 2 < self.members.len() {
+                let high = self.members[i + 2];
+                found |= low <= c && c <= high;
+                i += 3;
+            } else {
+                found |= low == c;
+                i += 1;
+            }
+        }
+        found != self.negated
+    }
+}
+
+/// Parses a bracket expression that follows a '[', returning it and the number of pattern bytes
+/// it used including the closing ']'
+fn class(pattern: &[u8]) -> Option<(Class<'_>, usize)> {
+    let negated = matches!(pattern.first(), Some(b'!') | Some(b'^'));
+    let start = negated as usize;
+    // A ']' straight after the opening bracket is a member rather than the end
+    let mut end = start + 1;
+    while end < pattern.len() && pattern[end] != b']' {
+        if pattern[end] == b'\\' {
+            end += 1;
+        }
+        end += 1;
+    }
+    if end >= pattern.len() {
+        return None;
+    }
+    Some((
+        Class {
+            members: &pattern[start..end],
+            negated,
+        },
+        end + 1,
+    ))
+}
+
+/// Contents of the file named by `core.excludesFile`, or git's default of
+/// `$XDG_CONFIG_HOME/git/ignore`
+fn global_excludes() -> Option<Vec<u8>> {
+    let home = fs::getenv(b"HOME\0");
+    let config_home = match fs::getenv(b"XDG_CONFIG_HOME\0") {
+        Some(xdg) if !xdg.is_empty() => xdg.to_vec(),
+        _ => [home?, b"/.config"].concat(),
+    };
+
+    // ~/.gitconfig is read after the XDG config file, so it wins
+    let mut configured = None;
+    for config in &[
+        [&config_home[..], b"/git/config"].concat(),
+        [home.unwrap_or(b""), b"/.gitconfig"].concat(),
+    ] {
+        if let Some(path) = fs::read(config).and_then(|c| excludes_file(&c, home)) {
+            configured = Some(path);
+        }
+    }
+
+    fs::read(&configured.unwrap_or_else(|| [&config_home[..], b"/git/ignore"].concat()))
+}
+
+fn excludes_file(config: &[u8], home: Option<&[u8]>) -> Option<Vec<u8>> {
+    let mut in_core = false;
+    let mut path = None;
+    for line in config.split(|b| *b == b'\n') {
+        let line = git::trim(line);
+        if line.first() == Some(&b'[') {
+            let section = line[1..].split(|b| *b == b']').next().unwrap_or(b"");
+            in_core = git::trim(section).eq_ignore_ascii_case(b"core");
+            continue;
+        }
+        if !in_core {
+            continue;
+        }
+        let mut key_value = line.splitn(2, |b| *b == b'=');
+        if let (Some(key), Some(value)) = (key_value.next(), key_value.next()) {
+            if !git::trim(key).eq_ignore_ascii_case(b"excludesfile") {
+                continue;
+            }
+            let mut value = git::trim(value);
+            if value.len() >= 2 && value[0] == b'"' && value[value.len() - 1] == b'"' {
+                value = &value[1..value.len() - 1];
+            }
+            path = Some(match home {
+                Some(home) if value.starts_with(b"~/") => [home, &value[1..]].concat(),
+                _ => value.to_vec(),
+            });
+        }
+    }
+    path
+}
@@ -0,0 +1,235 @@
+//! A small DEFLATE decoder, enough to read git objects without linking zlib.
+//! The structure follows Mark Adler's puff.c.
+
+use alloc::vec::Vec;
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a zlib stream, ignoring the trailing checksum
+pub fn zlib(input: &[u8]) -> Option<Vec<u8>> {
+    let header = input.get(..2)?;
+    // Compression method 8 is deflate, and a preset dictionary is never used by git
+    if header[0] & 0x0f != 8 || header[1] & 0x20 != 0 {
+        return None;
+    }
+    inflate(&input[2..])
+}
+
+/// Decompresses a raw DEFLATE stream. Input after the final block is ignored.
+pub fn inflate(input: &[u8]) -> Option<Vec<u8>> {
+    let mut state = State {
+        input,
+        pos: 0,
+        bit_buf: 0,
+        bit_count: 0,
+        out: Vec::with_capacity(input.len() * 2),
+    };
+
+    loop {
+        let last = state.bits(1)? == 1;
+        match state.bits(2)? {
+            0 => state.stored()?,
+            1 => {
+                let (lengths, distances) = fixed_tables();
+                state.codes(&lengths, &distances)?
+            }
+            2 => {
+                let (lengths, distances) = state.dynamic_tables()?;
+                state.codes(&lengths, &distances)?
+            }
+            _ => return None,
+        }
+        if last {
+            return Some(state.out);
+        }
+    }
+}
+
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: [u16; 288],
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut huffman = Huffman {
+            counts: [0; MAX_BITS + 1],
+            symbols: [0; 288],
+        };
+        for &len in lengths {
+            huffman.counts[len as usize] += 1;
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + huffman.counts[len];
+        }
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                huffman.symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        huffman
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+struct State<'a> {
+    input: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+    out: Vec<u8>,
+}
+
+impl<'a> State<'a> {
+    fn bits(&mut self, need: u32) -> Option<u32> {
+        let mut value = self.bit_buf;
+        while self.bit_count < need {
+            let byte = *self.input.get(self.pos)?;
+            self.pos += 1;
+            value |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        self.bit_buf = if need == 32 { 0 } else { value >> need };
+        self.bit_count -= need;
+        Some(value & ((1u64 << need) - 1) as u32)
+    }
+
+    fn stored(&mut self) -> Option<()> {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+        let header = self.input.get(self.pos..self.pos + 4)?;
+        let len = u16::from_le_bytes([header[0], header[1]]);
+        let complement = u16::from_le_bytes([header[2], header[3]]);
+        if len != !complement {
+            return None;
+        }
+        self.pos += 4;
+        let data = self.input.get(self.pos..self.pos + len as usize)?;
+        self.out.extend_from_slice(data);
+        self.pos += len as usize;
+        Some(())
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Option<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= self.bits(1)? as i32;
+            let count = huffman.counts[len] as i32;
+            if code - count < first {
+                return huffman
+                    .symbols
+                    .get((index + (code - first)) as usize)
+                    .copied();
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        None
+    }
+
+    fn codes(&mut self, lengths: &Huffman, distances: &Huffman) -> Option<()> {
+        loop {
+            let symbol = self.decode(lengths)? as usize;
+            if symbol < 256 {
+                self.out.push(symbol as u8);
+            } else if symbol == 256 {
+                return Some(());
+            } else {
+                let symbol = symbol - 257;
+                let len = *LENGTH_BASE.get(symbol)? as usize
+                    + self.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = self.decode(distances)? as usize;
+                let distance = *DISTANCE_BASE.get(symbol)? as usize
+                    + self.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+                if distance > self.out.len() {
+                    return None;
+                }
+
+                // The source and destination may overlap, so this has to go byte by byte
+                let start = self.out.len() - distance;
+                for i in 0..len {
+                    let byte = self.out[start + i];
+                    self.out.push(byte);
+                }
+            }
+        }
+    }
+
+    fn dynamic_tables(&mut self) -> Option<(Huffman, Huffman)> {
+        let num_lengths = self.bits(5)? as usize + 257;
+        let num_distances = self.bits(5)? as usize + 1;
+        let num_code_lengths = self.bits(4)? as usize + 4;
+        if num_lengths > 286 || num_distances > 30 {
+            return None;
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &i in CODE_LENGTH_ORDER.iter().take(num_code_lengths) {
+            code_lengths[i] = self.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths);
+
+        let mut lengths = [0u8; 286 + 30];
+        let mut i = 0;
+        while i < num_lengths + num_distances {
+            let symbol = self.decode(&code_lengths)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => (*lengths.get(i.checked_sub(1)?)?, 3 + self.bits(2)?),
+                17 => (0, 3 + self.bits(3)?),
+                _ => (0, 11 + self.bits(7)?),
+            };
+            for _ in 0..repeat {
+                *lengths.get_mut(i)? = value;
+                i += 1;
+            }
+        }
+        if i != num_lengths + num_distances || lengths[256] == 0 {
+            return None;
+        }
+
+        Some((
+            Huffman::new(&lengths[..num_lengths]),
+            Huffman::new(&lengths[num_lengths..num_lengths + num_distances]),
+        ))
+    }
+}
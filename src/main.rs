@@ -30,16 +30,27 @@ extern crate alloc;
 use alloc::vec::Vec;
 
 pub mod cli;
+mod completions;
+mod diagnostics;
 mod directory;
 mod error;
+mod fs;
+mod git;
+mod ids;
+mod ignore;
+mod inflate;
 mod style;
+mod terminfo;
+mod theme;
+mod uring;
+mod walk;
 
-use cli::{DisplayMode, ShowAll, SortField};
+use cli::{DisplayMode, GitIgnore, SortField};
 use directory::DirEntry;
 use output::*;
 use style::Style;
 
-use veneer::directory::DType;
+use crate::fs::DType;
 use veneer::{syscalls, CStr, Error};
 
 #[no_mangle]
@@ -48,11 +59,11 @@ unsafe extern "C" fn main(argc: isize, argv: *const *const libc::c_char) -> i32
 
     match run(args) {
         Ok(()) => 0,
-        Err(e) => e.0 as i32,
+        Err(e) => e.exit_code(),
     }
 }
 
-fn run(args: Vec<CStr<'static>>) -> Result<(), Error> {
+fn run(args: Vec<CStr<'static>>) -> Result<(), error::Error> {
     let mut app = cli::App::from_arguments(args)?;
 
     let need_details = app.display_mode == DisplayMode::Long
@@ -67,30 +78,62 @@ fn run(args: Vec<CStr<'static>>) -> Result<(), Error> {
 
     if app.list_directory_contents {
         for arg in app.args.clone() {
-            match veneer::Directory::open(arg) {
+            let is_link = || {
+                syscalls::lstatat(libc::AT_FDCWD, arg)
+                    .map(|status| status.st_mode & libc::S_IFMT == libc::S_IFLNK)
+                    .unwrap_or(false)
+            };
+            if app.follow_symlinks == cli::FollowSymlinks::Never && is_link() {
+                files.push(crate::directory::File::new(arg, &app));
+                continue;
+            }
+            match fs::Directory::open(arg) {
                 Ok(d) => dirs.push((arg, d)),
-                Err(Error(20)) => files.push(crate::directory::File { path: arg }),
+                Err(Error(20)) => files.push(crate::directory::File::new(arg, &app)),
                 Err(e) => {
-                    let mut buf = itoa::Buffer::new();
-                    error!(
-                        b"OS error ",
-                        buf.format(e.0).as_bytes(),
-                        b": ",
-                        e.msg().as_bytes(),
-                        b" \"",
-                        arg.as_bytes(),
-                        b"\""
-                    );
+                    // Only something that exists but will not open is worth calling a directory
+                    match syscalls::fstatat(libc::AT_FDCWD, arg) {
+                        Ok(_) => diagnostics::cannot_open_directory(arg.as_bytes(), e),
+                        // A broken link is only an error if it should have been followed
+                        Err(_)
+                            if app.follow_symlinks
+                                == cli::FollowSymlinks::DirectoriesWhenExplicit
+                                && is_link() =>
+                        {
+                            files.push(crate::directory::File::new(arg, &app));
+                            continue;
+                        }
+                        Err(e) => diagnostics::cannot_access(arg.as_bytes(), e),
+                    }
+                    app.report(error::Error::Serious);
                 }
             }
         }
     } else {
         for arg in app.args.clone() {
-            files.push(crate::directory::File { path: arg })
+            // With details, operands are stat'd anyway and the missing ones reported then
+            if !need_details {
+                let status = match app.follow_symlinks {
+                    cli::FollowSymlinks::Always | cli::FollowSymlinks::WhenExplicit => {
+                        syscalls::fstatat(libc::AT_FDCWD, arg)
+                    }
+                    _ => syscalls::lstatat(libc::AT_FDCWD, arg),
+                };
+                if let Err(e) = status {
+                    diagnostics::cannot_access(arg.as_bytes(), e);
+                    app.report(error::Error::Serious);
+                    continue;
+                }
+            }
+            files.push(crate::directory::File::new(arg, &app))
         }
     }
 
     if !files.is_empty() {
+        if app.show_git_status {
+            app.git = git::Repo::new(CStr::from_bytes(b".\0"));
+            app.ignore = ignore::Ignore::new(CStr::from_bytes(b".\0"));
+        }
         if !need_details {
             files.sort_unstable_by(|a, b| {
                 let mut ordering = vercmp(a.name(), b.name());
@@ -100,7 +143,7 @@ fn run(args: Vec<CStr<'static>>) -> Result<(), Error> {
                 ordering
             });
 
-            let dir = veneer::Directory::open(CStr::from_bytes(b".\0")).unwrap();
+            let dir = fs::Directory::open(CStr::from_bytes(b".\0")).unwrap();
             match app.display_mode {
                 DisplayMode::Grid(width) => write_grid(&files, &dir, &mut app, width),
                 DisplayMode::SingleColumn => write_single_column(&files, &dir, &mut app),
@@ -109,9 +152,13 @@ fn run(args: Vec<CStr<'static>>) -> Result<(), Error> {
             }
         } else {
             let mut files_and_stats = Vec::with_capacity(files.len());
-            let dir = veneer::Directory::open(CStr::from_bytes(b".\0")).unwrap();
+            let dir = fs::Directory::open(CStr::from_bytes(b".\0")).unwrap();
             for e in files.iter().cloned() {
-                let status = if app.follow_symlinks == cli::FollowSymlinks::Always {
+                let follow = matches!(
+                    app.follow_symlinks,
+                    cli::FollowSymlinks::Always | cli::FollowSymlinks::WhenExplicit
+                );
+                let status = if follow {
                     syscalls::fstatat(dir.raw_fd(), e.name())
                 } else {
                     syscalls::lstatat(dir.raw_fd(), e.name())
@@ -120,12 +167,8 @@ fn run(args: Vec<CStr<'static>>) -> Result<(), Error> {
                 match status {
                     Ok(s) => files_and_stats.push((e, s)),
                     Err(err) => {
-                        error!(
-                            b"Unable to access '",
-                            e.name().as_bytes(),
-                            b"': ",
-                            err.msg().as_bytes()
-                        );
+                        diagnostics::cannot_access(e.name().as_bytes(), err);
+                        app.report(error::Error::Serious);
                     }
                 }
             }
@@ -165,63 +208,198 @@ fn run(args: Vec<CStr<'static>>) -> Result<(), Error> {
         app.out.push(b'\n');
     }
 
-    for (n, (name, dir)) in dirs.iter().enumerate() {
-        list_dir_contents(multiple_args, need_details, *name, dir, &mut app);
-        // When recursing the recursion handles newlines, if not we need to check if we're on the
-        // last and print a newline
-        if !app.recurse && (n != dirs.len() - 1) {
+    let count = dirs.len();
+    for (n, (name, dir)) in dirs.into_iter().enumerate() {
+        app.ignore = match app.git_ignore {
+            GitIgnore::Off if !app.show_git_status => None,
+            _ => ignore::Ignore::new(name),
+        };
+        if app.show_git_status {
+            app.git = git::Repo::new(name);
+        }
+        if app.one_file_system {
+            app.root_device = dir.status().map(|s| s.st_dev).unwrap_or(0);
+        }
+        let anchor = Anchor {
+            dirfd: libc::AT_FDCWD,
+            path: name.as_bytes(),
+        };
+        list_dir_contents(
+            multiple_args,
+            need_details,
+            name,
+            dir,
+            anchor,
+            None,
+            &mut app,
+        );
+        if n != count - 1 {
             app.out.push(b'\n');
         }
     }
 
-    Ok(())
+    if let Some(dired) = app.dired.take() {
+        dired.write_trailer(&mut app);
+    }
+
+    match app.problem {
+        Some(problem) => Err(problem),
+        None => Ok(()),
+    }
+}
+
+/// Bytes of directory records read at a time when streaming, and by the walker when it reads a
+/// directory ahead. This bounds memory use, and how far ahead long mode looks to size its columns.
+const STREAM_WINDOW: usize = 512 * 1024;
+
+/// Where a directory can be opened from again: a path relative to a directory that is still open.
+/// Holding on to every directory on the way down would take a file descriptor per level, so only
+/// one is kept every ANCHOR_SPACING bytes of path, which also keeps paths well short of PATH_MAX.
+#[derive(Clone, Copy)]
+struct Anchor<'a> {
+    dirfd: libc::c_int,
+    /// Empty for the directory `dirfd` itself
+    path: &'a [u8],
 }
 
+const ANCHOR_SPACING: usize = 2048;
+
 fn list_dir_contents(
     multiple_args: bool,
     need_details: bool,
     name: CStr,
-    dir: &veneer::Directory,
+    dir: fs::Directory,
+    anchor: Anchor,
+    prefetched: Option<walk::Listing>,
     app: &mut cli::App,
 ) {
-    let contents = match dir.read() {
+    // Following symlinks can lead back into a directory we are still listing, so with -L every
+    // directory on the way down is remembered by its device and inode
+    let identity = if app.recurse && app.follow_symlinks == cli::FollowSymlinks::Always {
+        dir.status()
+            .ok()
+            .map(|status| (status.st_dev, status.st_ino))
+    } else {
+        None
+    };
+    if let Some(identity) = identity {
+        if app.ancestors.contains(&identity) {
+            diagnostics::already_listed(name.as_bytes());
+            app.report(error::Error::Serious);
+            return;
+        }
+        app.ancestors.push(identity);
+    }
+
+    // Subdirectories are separated from whatever came before, but only once it is certain that
+    // they will be listed
+    if app.depth > 0 {
+        app.out.push(b'\n');
+    }
+
+    list_entries(
+        multiple_args,
+        need_details,
+        name,
+        dir,
+        anchor,
+        prefetched,
+        app,
+    );
+
+    if identity.is_some() {
+        app.ancestors.pop();
+    }
+}
+
+fn list_entries(
+    multiple_args: bool,
+    need_details: bool,
+    name: CStr,
+    dir: fs::Directory,
+    anchor: Anchor,
+    prefetched: Option<walk::Listing>,
+    app: &mut cli::App,
+) {
+    let (contents, mut stats) = match prefetched {
+        Some(listing) => (listing.contents, listing.stats),
+        None if can_stream(need_details, app) => (dir.read_some(STREAM_WINDOW), None),
+        None => (dir.read().map(|contents| (contents, true)), None),
+    };
+    let contents = match contents {
+        Ok((contents, true)) => Ok(contents),
+        Ok((first, false)) if can_stream(need_details, app) => {
+            stream_entries(multiple_args, need_details, name, dir, anchor, first, app);
+            return;
+        }
+        // Read ahead only as far as one window, and this listing needs all of it at once
+        Ok((mut contents, false)) => dir.read().map(|rest| {
+            contents.append(rest);
+            contents
+        }),
+        Err(err) => Err(err),
+    };
+    let contents = match contents {
         Ok(c) => c,
         Err(err) => {
-            error!(
-                b"Unable to access '",
-                name.as_bytes(),
-                b"': ",
-                err.msg().as_bytes()
-            );
+            diagnostics::cannot_read_directory(name.as_bytes(), err);
+            app.report(app.directory_problem());
             return;
         }
     };
+
     let hint = contents.iter().size_hint();
     let mut entries = Vec::new();
     entries.reserve(hint.1.unwrap_or(hint.0));
 
-    match app.show_all {
-        ShowAll::No => {
-            for e in contents.iter().filter(|e| e.name().get(0) != Some(b'.')) {
-                entries.push(e)
-            }
-        }
-        ShowAll::Almost => {
-            for e in contents.iter() {
-                if e.name().as_bytes() != b".." && e.name().as_bytes() != b"." {
-                    entries.push(e)
+    for e in contents
+        .iter()
+        .filter(|e| app.show_all.shows(e.name().as_bytes()))
+    {
+        entries.push(e);
+    }
+
+    if let Some(ignore) = app.ignore.as_mut() {
+        let is_dir = |e: &fs::Entry| match e.d_type() {
+            DType::DIR => true,
+            DType::UNKNOWN => syscalls::lstatat(dir.raw_fd(), e.name())
+                .map(|s| s.st_mode & libc::S_IFMT == libc::S_IFDIR)
+                .unwrap_or(false),
+            _ => false,
+        };
+        match app.git_ignore {
+            GitIgnore::Off => {}
+            GitIgnore::Hide => {
+                let hidden: Vec<bool> = entries
+                    .iter()
+                    .map(|e| ignore.is_ignored(e.name().as_bytes(), is_dir(e)))
+                    .collect();
+                // Prefetched stats line up with the entries, so they have to go too
+                let mut flags = hidden.iter();
+                entries.retain(|_| flags.next() == Some(&false));
+                if let Some(stats) = stats.as_mut() {
+                    let mut flags = hidden.iter();
+                    stats.retain(|_| flags.next() == Some(&false));
                 }
             }
-        }
-        ShowAll::Yes => {
-            for e in contents.iter() {
-                entries.push(e);
+            GitIgnore::Dim => {
+                let dimmed = entries
+                    .iter()
+                    .filter(|e| ignore.is_ignored(e.name().as_bytes(), is_dir(e)))
+                    .map(|e| e.name().as_bytes().to_vec())
+                    .collect();
+                ignore.set_dimmed(dimmed);
             }
         }
     }
 
     if multiple_args || app.recurse {
-        print!(app, name, ":\n");
+        write_header(app, name);
+    }
+
+    // Only a work tree root has a .git entry, so other directories pay nothing for this
+    if app.show_git_status && contents.iter().any(|e| e.name().as_bytes() == b".git") {
+        write_git_head(name, app);
     }
 
     if !need_details {
@@ -242,47 +420,16 @@ fn list_dir_contents(
         }
 
         if app.recurse {
-            app.out.push(b'\n');
-            for e in entries
+            let subdirs: Vec<CStr> = entries
                 .into_iter()
-                .filter(|e| e.d_type() == DType::DIR || e.d_type() == DType::UNKNOWN)
-                .filter(|e| e.name().as_bytes() != b"..")
-                .filter(|e| e.name().as_bytes() != b".")
-            {
-                let mut path = name.as_bytes().to_vec();
-                if path.last() != Some(&b'/') {
-                    path.push(b'/');
-                }
-                path.extend_from_slice(e.name().as_bytes());
-                path.push(0);
-                let path = CStr::from_bytes(&path);
-                if let Ok(dir) = veneer::Directory::open(path) {
-                    list_dir_contents(multiple_args, need_details, path, &dir, app);
-                }
-            }
+                .filter(|e| may_be_subdirectory(e, &dir, app))
+                .map(|e| e.name())
+                .collect();
+            let parent = (name, dir, anchor);
+            list_subdirectories(multiple_args, need_details, parent, &subdirs, false, app);
         }
     } else {
-        let mut entries_and_stats = Vec::with_capacity(entries.len());
-        for e in entries.iter().cloned() {
-            let status = if app.follow_symlinks == cli::FollowSymlinks::Always {
-                syscalls::fstatat(dir.raw_fd(), e.name())
-            } else {
-                syscalls::lstatat(dir.raw_fd(), e.name())
-            }
-            .map(|status| app.convert_status(status));
-            match status {
-                Ok(s) => entries_and_stats.push((e, s)),
-                Err(err) => {
-                    error!(
-                        b"Unable to access '",
-                        e.name().as_bytes(),
-                        b"': ",
-                        err.msg().as_bytes(),
-                        b"\n"
-                    );
-                }
-            }
-        }
+        let mut entries_and_stats = stat_entries(&entries, stats, name, &dir, app);
 
         if let Some(field) = app.sort_field {
             entries_and_stats.sort_unstable_by(|a, b| {
@@ -314,32 +461,426 @@ fn list_dir_contents(
         }
 
         if app.recurse {
-            app.out.push(b'\n');
-            for e in entries_and_stats
-                .into_iter()
-                .filter_map(|(e, status)| {
-                    if status.mode & libc::S_IFMT == libc::S_IFDIR {
-                        Some(e)
-                    } else {
-                        None
-                    }
-                })
-                .filter(|e| e.name().as_bytes() != b"..")
-                .filter(|e| e.name().as_bytes() != b".")
-            {
-                let mut path = name.as_bytes().to_vec();
-                if path.last() != Some(&b'/') {
-                    path.push(b'/');
-                }
-                path.extend_from_slice(e.name().as_bytes());
-                path.push(0);
-                let path = CStr::from_bytes(&path);
-                if let Ok(dir) = veneer::Directory::open(path) {
-                    list_dir_contents(multiple_args, need_details, path, &dir, app);
+            let subdirs: Vec<CStr> = entries_and_stats
+                .iter()
+                .filter(|(e, status)| is_subdirectory(e, status, app))
+                .map(|(e, _)| e.name())
+                .collect();
+            let parent = (name, dir, anchor);
+            list_subdirectories(multiple_args, need_details, parent, &subdirs, true, app);
+        }
+    }
+}
+
+/// Unsorted single column and long listings can be written as the directory is read, without
+/// holding all of it in memory. Ignore rules need the whole directory, so --gitignore rules it
+/// out, and so does --git, which loads them to tell ignored files from new ones.
+fn can_stream(need_details: bool, app: &cli::App) -> bool {
+    app.sort_field.is_none()
+        && app.ignore.is_none()
+        && match app.display_mode {
+            DisplayMode::Long => true,
+            DisplayMode::SingleColumn => !need_details,
+            _ => false,
+        }
+}
+
+/// Lists a directory too large to read in one go, one window of entries at a time. Columns are
+/// sized by what has been seen so far, so they can only grow. The total line of long mode comes
+/// before the entries it adds up, so they are read once to count blocks and again to list them,
+/// and the listing reuses as many of the stats taken for the count as were kept.
+fn stream_entries(
+    multiple_args: bool,
+    need_details: bool,
+    name: CStr,
+    dir: fs::Directory,
+    anchor: Anchor,
+    first: fs::Contents,
+    app: &mut cli::App,
+) {
+    if multiple_args || app.recurse {
+        write_header(app, name);
+    }
+    if app.show_git_status && syscalls::lstatat(dir.raw_fd(), CStr::from_bytes(b".git\0")).is_ok() {
+        write_git_head(name, app);
+    }
+
+    let mut window = first;
+    let mut kept = Vec::new().into_iter();
+    if app.display_mode == DisplayMode::Long {
+        match count_blocks(&dir, window, app) {
+            Ok((blocks, first, stats)) => {
+                write_total(blocks, app);
+                window = first;
+                kept = stats.into_iter();
+            }
+            Err(err) => {
+                diagnostics::cannot_read_directory(name.as_bytes(), err);
+                app.report(app.directory_problem());
+                return;
+            }
+        }
+    }
+
+    // Each window is gone by the time recursion starts, so subdirectory names are copied out
+    let mut subdirs: Vec<Vec<u8>> = Vec::new();
+    let mut columns = Columns::default();
+    let mut done = false;
+    loop {
+        let entries: Vec<fs::Entry> = window
+            .iter()
+            .filter(|e| app.show_all.shows(e.name().as_bytes()))
+            .collect();
+        if need_details {
+            // A kept stat only stands for an entry with the same inode, in case the directory
+            // changed between the two reads
+            let stats = if kept.len() > 0 {
+                let stats = entries.iter().map(|e| match kept.next() {
+                    Some((inode, status)) if inode == e.inode() => status,
+                    _ => stat_at(&dir, e.name(), app),
+                });
+                Some(stats.collect())
+            } else {
+                None
+            };
+            let entries_and_stats = stat_entries(&entries, stats, name, &dir, app);
+            columns = columns.max(Columns::measure(&entries_and_stats, app));
+            write_details_lines(&entries_and_stats, &dir, columns, app);
+            if app.recurse {
+                subdirs.extend(
+                    entries_and_stats
+                        .iter()
+                        .filter(|(e, status)| is_subdirectory(e, status, app))
+                        .map(|(e, _)| fs::nul_terminated(e.name().as_bytes())),
+                );
+            }
+        } else {
+            write_single_column(&entries, &dir, app);
+            if app.recurse {
+                subdirs.extend(
+                    entries
+                        .iter()
+                        .filter(|e| may_be_subdirectory(e, &dir, app))
+                        .map(|e| fs::nul_terminated(e.name().as_bytes())),
+                );
+            }
+        }
+
+        if done {
+            break;
+        }
+        match dir.read_some(STREAM_WINDOW) {
+            Ok((next, complete)) => {
+                window = next;
+                done = complete;
+            }
+            Err(err) => {
+                diagnostics::cannot_read_directory(name.as_bytes(), err);
+                app.report(app.directory_problem());
+                break;
+            }
+        }
+    }
+
+    if app.recurse {
+        let subdirs: Vec<CStr> = subdirs.iter().map(|n| CStr::from_bytes(n)).collect();
+        let parent = (name, dir, anchor);
+        list_subdirectories(
+            multiple_args,
+            need_details,
+            parent,
+            &subdirs,
+            need_details,
+            app,
+        );
+    }
+}
+
+/// How many stats of a directory being streamed `count_blocks` keeps for the listing, which bounds
+/// the memory they take the way `STREAM_WINDOW` bounds the entries
+const KEPT_STATS: usize = 16 * 1024;
+
+/// Stats kept by `count_blocks`, each with the inode of the entry it is for
+type KeptStats = Vec<(u64, Result<libc::stat64, Error>)>;
+
+/// Adds up the blocks of every entry of a directory being streamed, starting from the window
+/// `first`, then rewinds it and returns its first window again, along with the inodes and stats of
+/// up to `KEPT_STATS` of the first entries. Entries that cannot be stat'd are left for the listing
+/// to report.
+fn count_blocks(
+    dir: &fs::Directory,
+    first: fs::Contents,
+    app: &cli::App,
+) -> Result<(i64, fs::Contents, KeptStats), Error> {
+    let mut blocks = 0;
+    let mut kept = Vec::new();
+    let mut window = first;
+    let mut done = false;
+    loop {
+        for e in window
+            .iter()
+            .filter(|e| app.show_all.shows(e.name().as_bytes()))
+        {
+            let status = stat_at(dir, e.name(), app);
+            if let Ok(status) = status {
+                blocks += total_blocks(&app.convert_status(status));
+            }
+            if kept.len() < KEPT_STATS {
+                kept.push((e.inode(), status));
+            }
+        }
+        if done {
+            break;
+        }
+        let (next, complete) = dir.read_some(STREAM_WINDOW)?;
+        window = next;
+        done = complete;
+    }
+    dir.rewind()?;
+    dir.read_some(STREAM_WINDOW)
+        .map(|(first, _)| (blocks, first, kept))
+}
+
+/// Stats `name` in `dir`, following it if it is a symlink and symlinks are always followed
+fn stat_at(dir: &fs::Directory, name: CStr, app: &cli::App) -> Result<libc::stat64, Error> {
+    if app.follow_symlinks == cli::FollowSymlinks::Always {
+        syscalls::fstatat(dir.raw_fd(), name)
+    } else {
+        syscalls::lstatat(dir.raw_fd(), name)
+    }
+}
+
+/// Stats each entry of `dir`, which is at `path`, using `stats` from a read-ahead if there are any,
+/// and reports the ones that fail
+fn stat_entries<'a>(
+    entries: &[fs::Entry<'a>],
+    stats: Option<Vec<Result<libc::stat64, Error>>>,
+    path: CStr,
+    dir: &fs::Directory,
+    app: &mut cli::App,
+) -> Vec<(fs::Entry<'a>, Status)> {
+    let mut entries_and_stats = Vec::with_capacity(entries.len());
+    let stats = match (stats, app.ring.as_mut()) {
+        (None, Some(ring)) => {
+            let names: Vec<CStr> = entries.iter().map(|e| e.name()).collect();
+            let follow = app.follow_symlinks == cli::FollowSymlinks::Always;
+            let batch = ring.stat_all(dir.raw_fd(), &names, follow);
+            // Without statx support the ring would fail the same way for every directory
+            if batch.is_none() {
+                app.ring = None;
+            }
+            batch
+        }
+        (stats, _) => stats,
+    };
+    let mut stats = stats.map(Vec::into_iter);
+    for e in entries.iter().cloned() {
+        let status = match stats.as_mut().and_then(Iterator::next) {
+            Some(status) => status,
+            None => stat_at(dir, e.name(), app),
+        }
+        .map(|status| app.convert_status(status));
+        match status {
+            Ok(s) => entries_and_stats.push((e, s)),
+            Err(err) => {
+                let path = join_path(path.as_bytes(), e.name().as_bytes());
+                diagnostics::cannot_access(&path[..path.len() - 1], err);
+                app.report(error::Error::Minor);
+            }
+        }
+    }
+    entries_and_stats
+}
+
+/// `name` inside `parent`, nul-terminated
+fn join_path(parent: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut path = Vec::with_capacity(parent.len() + name.len() + 2);
+    path.extend_from_slice(parent);
+    if path.last() != Some(&b'/') {
+        path.push(b'/');
+    }
+    path.extend_from_slice(name);
+    path.push(0);
+    path
+}
+
+/// With --git, every directory listed that is the root of a work tree gets a line saying what is
+/// checked out, after its header if it has one
+fn write_git_head(name: CStr, app: &mut cli::App) {
+    if let Some(head) = git::Head::read(name.as_bytes()) {
+        if app.dired.is_some() {
+            app.out.write(b"  ");
+        }
+        print!(app, head, Style::Reset, "\n");
+    }
+}
+
+/// Whether recursion should try an entry, going by its d_type alone. With -L that is not enough
+/// for symlinks, which are only worth trying if they lead to a directory.
+fn may_be_subdirectory(e: &fs::Entry, dir: &fs::Directory, app: &cli::App) -> bool {
+    let candidate = match e.d_type() {
+        DType::DIR | DType::UNKNOWN => true,
+        DType::LNK if app.follow_symlinks == cli::FollowSymlinks::Always => {
+            syscalls::fstatat(dir.raw_fd(), e.name())
+                .map(|status| status.st_mode & libc::S_IFMT == libc::S_IFDIR)
+                .unwrap_or(false)
+        }
+        _ => false,
+    };
+    candidate && e.name().as_bytes() != b".." && e.name().as_bytes() != b"."
+}
+
+/// Whether recursion should go into an entry that has been stat'd
+fn is_subdirectory(e: &fs::Entry, status: &Status, app: &cli::App) -> bool {
+    let same_device = !app.one_file_system || status.device == app.root_device;
+    status.mode & libc::S_IFMT == libc::S_IFDIR
+        && same_device
+        && e.name().as_bytes() != b".."
+        && e.name().as_bytes() != b"."
+}
+
+/// Lists the subdirectories `names` of `parent`, given as its path, the directory itself and how
+/// to open it again
+fn list_subdirectories(
+    multiple_args: bool,
+    need_details: bool,
+    parent: (CStr, fs::Directory, Anchor),
+    names: &[CStr],
+    devices_checked: bool,
+    app: &mut cli::App,
+) {
+    let (parent_path, parent, anchor) = parent;
+    let follow = app.follow_symlinks == cli::FollowSymlinks::Always;
+    // Skipped mounts are left alone entirely, rather than read ahead and then thrown away
+    let names: Vec<CStr> = names
+        .iter()
+        .copied()
+        .filter(|name| may_enter(&parent, *name, !devices_checked, app))
+        .collect();
+    if names.is_empty() {
+        return;
+    }
+    // The parent is closed before going down unless it is needed as a new anchor
+    let parent = if anchor.path.len() >= ANCHOR_SPACING {
+        Some(parent)
+    } else {
+        drop(parent);
+        None
+    };
+    let anchor = match &parent {
+        Some(parent) => Anchor {
+            dirfd: parent.raw_fd(),
+            path: b"",
+        },
+        None => anchor,
+    };
+    let relative_paths: Vec<Vec<u8>> = names
+        .iter()
+        .map(|name| match anchor.path {
+            b"" => fs::nul_terminated(name.as_bytes()),
+            path => join_path(path, name.as_bytes()),
+        })
+        .collect();
+
+    let options = walk::Options {
+        stat: need_details,
+        follow_symlinks: follow,
+        show_all: app.show_all,
+    };
+    // Subdirectories are read ahead as far as the walker's budget allows, which is shared with
+    // every level below this one, and the rest are opened here when their turn comes
+    let mut jobs: Vec<Option<walk::JobId>> = Vec::new();
+
+    for (i, name) in names.iter().enumerate() {
+        // The full path is only needed for the header and messages
+        let path = join_path(parent_path.as_bytes(), name.as_bytes());
+        let path = CStr::from_bytes(&path);
+        let relative_path = CStr::from_bytes(&relative_paths[i]);
+
+        if let Some(walker) = app.walker.as_mut() {
+            while let Some(next) = relative_paths.get(jobs.len()) {
+                let next = CStr::from_bytes(next);
+                match walker.submit(jobs.len(), anchor.dirfd, next, options) {
+                    Some(job) => jobs.push(Some(job)),
+                    None => break,
                 }
             }
+            if jobs.len() == i {
+                jobs.push(None);
+            }
+        }
+        let opened = match (app.walker.as_mut(), jobs.get(i).copied().flatten()) {
+            (Some(walker), Some(job)) => walker
+                .take(job)
+                .map(|prefetched| (prefetched.dir, Some(prefetched.listing))),
+            _ => fs::Directory::open_at(anchor.dirfd, relative_path, follow).map(|dir| (dir, None)),
+        };
+        let (dir, listing) = match opened {
+            Ok(opened) => opened,
+            // Without a stat, anything of unknown type is tried, and symlinks are refused when
+            // not following them
+            Err(Error(e))
+                if e == libc::ENOTDIR as isize || (e == libc::ELOOP as isize && !follow) =>
+            {
+                continue
+            }
+            Err(err) => {
+                diagnostics::cannot_open_directory(path.as_bytes(), err);
+                app.report(error::Error::Minor);
+                continue;
+            }
+        };
+
+        if let Some(walker) = app.walker.as_mut() {
+            walker.descend(i);
+        }
+        let mark = app
+            .ignore
+            .as_mut()
+            .map(|ignore| ignore.enter(name.as_bytes(), dir.raw_fd()));
+        let git_mark = app.git.as_mut().map(|git| git.enter(name.as_bytes()));
+        app.depth += 1;
+        let child = Anchor {
+            dirfd: anchor.dirfd,
+            path: relative_path.as_bytes(),
+        };
+        list_dir_contents(multiple_args, need_details, path, dir, child, listing, app);
+        app.depth -= 1;
+        if let (Some(ignore), Some(mark)) = (app.ignore.as_mut(), mark) {
+            ignore.leave(mark);
+        }
+        if let (Some(git), Some(mark)) = (app.git.as_mut(), git_mark) {
+            git.leave(mark);
+        }
+        if let Some(walker) = app.walker.as_mut() {
+            walker.ascend();
+        }
+    }
+}
+
+/// Applies --one-file-system and --skip-fs to the subdirectory `name` of `parent` before it is
+/// opened. The device only needs to be looked up here if it did not come with the entry's stat.
+/// Anything that cannot be looked up is let through, so that opening it reports the problem.
+fn may_enter(parent: &fs::Directory, name: CStr, check_device: bool, app: &cli::App) -> bool {
+    let follow = app.follow_symlinks == cli::FollowSymlinks::Always;
+    if check_device && app.one_file_system {
+        let status = if follow {
+            syscalls::fstatat(parent.raw_fd(), name)
+        } else {
+            syscalls::lstatat(parent.raw_fd(), name)
+        };
+        match status {
+            Ok(status) if status.st_dev != app.root_device => return false,
+            _ => {}
+        }
+    }
+    if !app.skip_filesystems.is_empty() {
+        match fs::filesystem_type_at(parent.raw_fd(), name, follow) {
+            Ok(magic) if app.skip_filesystems.contains(&magic) => return false,
+            _ => {}
         }
     }
+    true
 }
 
 pub struct Status {
@@ -351,5 +892,8 @@ pub struct Status {
     pub uid: libc::uid_t,
     pub gid: libc::gid_t,
     pub time: libc::time_t,
+    pub mtime: libc::time_t,
+    pub mtime_nsec: i64,
     pub inode: libc::ino_t,
+    pub device: libc::dev_t,
 }
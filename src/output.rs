@@ -1,5 +1,6 @@
-use crate::cli::App;
+use crate::cli::{App, Color, SortDirection, TimeStyle};
 use crate::directory::DirEntry;
+use crate::style::Depth;
 use crate::{Status, Style};
 use alloc::vec::Vec;
 
@@ -21,121 +22,202 @@ macro_rules! error {
         let mut err = Vec::new();
         err.extend_from_slice(b"fls: ");
         $(err.extend($item);)*
-        if err.last() != Some(&b'\n') {
-            err.push(b'\n');
-        }
+        err.push(b'\n');
         let _ = veneer::syscalls::write(2, &err[..]);
     }};
 }
 
-pub fn write_details<T: DirEntry>(entries: &[(T, Status)], dir: &veneer::Directory, app: &mut App) {
-    use Style::*;
+/// Byte offsets recorded for Emacs' dired mode, as pairs of start and end positions
+#[derive(Default)]
+pub struct Dired {
+    pub names: Vec<usize>,
+    pub subdirs: Vec<usize>,
+}
 
-    let get_name = |id: libc::uid_t| unsafe {
-        core::ptr::NonNull::new(libc::getpwuid(id)).map(|pw| {
-            veneer::CStr::from_ptr(pw.as_ref().pw_name)
-                .as_bytes()
-                .to_vec()
-        })
-    };
+impl Dired {
+    /// Writes the trailer that tells dired where every name is, the same way GNU ls does
+    pub fn write_trailer(&self, app: &mut App) {
+        for (label, offsets) in &[("//DIRED//", &self.names), ("//SUBDIRED//", &self.subdirs)] {
+            if !offsets.is_empty() {
+                print!(app, label);
+                for offset in offsets.iter() {
+                    print!(app, " ", *offset as u64);
+                }
+                app.out.push(b'\n');
+            }
+        }
+        print!(app, "//DIRED-OPTIONS// --quoting-style=literal\n");
+    }
+}
 
-    let get_group = |id: libc::gid_t| unsafe {
-        core::ptr::NonNull::new(libc::getgrgid(id)).map(|gr| {
-            veneer::CStr::from_ptr(gr.as_ref().gr_name)
-                .as_bytes()
-                .to_vec()
-        })
-    };
+/// Writes `name` and records where it is in `offsets` when in dired mode
+fn write_recorded_name(app: &mut App, name: veneer::CStr, subdir: bool) {
+    let start = app.out.position();
+    app.out.write(name.as_bytes());
+    let end = app.out.position();
+    if let Some(dired) = app.dired.as_mut() {
+        let offsets = if subdir {
+            &mut dired.subdirs
+        } else {
+            &mut dired.names
+        };
+        offsets.push(start);
+        offsets.push(end);
+    }
+}
 
-    let mut longest_name_len = 0;
-    let mut longest_group_len = 0;
-    let mut largest_size = 0;
-    let mut largest_links = 0;
-    let mut blocks = 0;
-    let mut inode_len = 0;
-    let mut blocks_len = 0;
+/// Writes the `name:` line before the contents of a directory
+pub fn write_header(app: &mut App, name: veneer::CStr) {
+    if app.dired.is_some() {
+        app.out.write(b"  ");
+    }
+    write_recorded_name(app, name, true);
+    app.out.write(b":\n");
+}
 
-    for (_, status) in entries {
-        if app.print_owner && !app.uid_names.iter().any(|(id, _)| *id == status.uid) {
-            let name = if app.convert_id_to_name {
-                get_name(status.uid)
-            } else {
-                None
+/// Widths of the long mode columns that are aligned across lines
+#[derive(Clone, Copy, Default)]
+pub struct Columns {
+    owner: usize,
+    group: usize,
+    size: usize,
+    links: usize,
+    inode: usize,
+    blocks: usize,
+}
+
+impl Columns {
+    pub fn measure<T>(entries: &[(T, Status)], app: &mut App) -> Self {
+        let mut longest_name_len = 0;
+        let mut longest_group_len = 0;
+        let mut largest_size = 0;
+        let mut largest_links = 0;
+        let mut largest_inode = 0;
+        let mut largest_blocks = 0;
+
+        for (_, status) in entries {
+            let mut buf = itoa::Buffer::new();
+            if app.print_owner {
+                let name = if app.convert_id_to_name {
+                    app.ids.user(status.uid)
+                } else {
+                    None
+                };
+                let len = name.map_or_else(|| buf.format(status.uid).len(), <[u8]>::len);
+                longest_name_len = longest_name_len.max(len);
             }
-            .unwrap_or_else(|| itoa::Buffer::new().format(status.uid).bytes().collect());
-            longest_name_len = longest_name_len.max(name.len());
-            app.uid_names.push((status.uid, name));
-        }
 
-        if app.print_group && !app.gid_names.iter().any(|(id, _)| *id == status.gid) {
-            let group = if app.convert_id_to_name {
-                get_group(status.gid)
-            } else {
-                None
+            if app.print_group {
+                let group = if app.convert_id_to_name {
+                    app.ids.group(status.gid)
+                } else {
+                    None
+                };
+                let len = group.map_or_else(|| buf.format(status.gid).len(), <[u8]>::len);
+                longest_group_len = longest_group_len.max(len);
             }
-            .unwrap_or_else(|| itoa::Buffer::new().format(status.gid).bytes().collect());
-            longest_group_len = longest_group_len.max(group.len());
-            app.gid_names.push((status.gid, group));
+
+            largest_size = largest_size.max(status.size as usize);
+            largest_links = largest_links.max(status.links as usize);
+            largest_inode = largest_inode.max(status.inode as usize);
+            largest_blocks = largest_blocks.max(status.blocks as usize);
+        }
+
+        let mut buf = itoa::Buffer::new();
+        Columns {
+            owner: longest_name_len,
+            group: longest_group_len,
+            size: buf.format(largest_size).len(),
+            links: buf.format(largest_links).len(),
+            inode: buf.format(largest_inode).len(),
+            blocks: buf.format(largest_blocks).len(),
         }
+    }
 
-        largest_size = largest_size.max(status.size as usize);
-        largest_links = largest_links.max(status.links as usize);
-        inode_len = inode_len.max(status.inode as usize);
-        blocks_len = blocks_len.max(status.blocks as usize);
-        blocks += status.blocks * status.block_size / 8192;
+    /// Widths wide enough for both `self` and `other`
+    pub fn max(self, other: Columns) -> Self {
+        Columns {
+            owner: self.owner.max(other.owner),
+            group: self.group.max(other.group),
+            size: self.size.max(other.size),
+            links: self.links.max(other.links),
+            inode: self.inode.max(other.inode),
+            blocks: self.blocks.max(other.blocks),
+        }
     }
+}
+
+pub fn write_details<T: DirEntry>(
+    entries: &[(T, Status)],
+    dir: &crate::fs::Directory,
+    app: &mut App,
+) {
+    let columns = Columns::measure(entries, app);
+    let blocks = entries.iter().map(|(_, status)| total_blocks(status)).sum();
+    write_total(blocks, app);
+    write_details_lines(entries, dir, columns, app);
+}
 
-    print!(app, "total ", blocks, "\n");
+/// What an entry adds to the total line of a long listing
+pub fn total_blocks(status: &Status) -> i64 {
+    status.blocks * status.block_size / 8192
+}
 
-    let mut buf = itoa::Buffer::new();
-    largest_size = buf.format(largest_size).len();
-    largest_links = buf.format(largest_links).len();
-    inode_len = buf.format(inode_len).len();
-    blocks_len = buf.format(blocks_len).len();
+pub fn write_total(blocks: i64, app: &mut App) {
+    // Every line is indented in dired mode
+    let indent: &[u8] = if app.dired.is_some() { b"  " } else { b"" };
+    print!(app, indent, "total ", blocks, "\n");
+}
+
+/// Writes the long mode line for each entry, without the total that comes before them
+pub fn write_details_lines<T: DirEntry>(
+    entries: &[(T, Status)],
+    dir: &crate::fs::Directory,
+    columns: Columns,
+    app: &mut App,
+) {
+    use Style::*;
+
+    let Columns {
+        owner: longest_name_len,
+        group: longest_group_len,
+        size: largest_size,
+        links: largest_links,
+        inode: inode_len,
+        blocks: blocks_len,
+    } = columns;
+    let indent: &[u8] = if app.dired.is_some() { b"  " } else { b"" };
 
     let current_time = unsafe { libc::time(core::ptr::null_mut()) };
     let one_year = 365 * 24 * 60 * 60;
+    let theme = app.theme;
 
     for direntry in entries {
         let e = &direntry.0;
         let status = &direntry.1;
         let mode = status.mode;
 
+        app.out.write(indent);
+
         if app.print_inode {
             app.out
-                .style(Magenta)
+                .style(theme.inode)
                 .align_right(status.inode as usize, inode_len)
                 .push(b' ');
         }
 
         if app.display_size_in_blocks {
             app.out
-                .style(White)
+                .style(theme.blocks)
                 .align_right(status.blocks as usize, blocks_len)
                 .push(b' ');
         }
 
-        let print_readable = |app: &mut App, mask| {
-            if mode & mask > 0 {
-                print!(app, GreenBold, "r");
-            } else {
-                print!(app, Gray, "-");
-            }
-        };
-
-        let print_writable = |app: &mut App, mask| {
-            if mode & mask > 0 {
-                print!(app, YellowBold, "w");
-            } else {
-                print!(app, Gray, "-");
-            }
-        };
-
-        let print_executable = |app: &mut App, mask| {
+        let print_permission = |app: &mut App, mask, style: Style, letter: &str| {
             if mode & mask > 0 {
-                print!(app, RedBold, "x");
+                print!(app, style, letter);
             } else {
-                print!(app, Gray, "-");
+                print!(app, theme.no_permission, "-");
             }
         };
 
@@ -148,82 +230,108 @@ pub fn write_details<T: DirEntry>(entries: &[(T, Status)], dir: &veneer::Directo
             }
         );
 
-        print_readable(app, S_IRUSR);
-        print_writable(app, S_IWUSR);
-        print_executable(app, S_IXUSR);
-
-        print_readable(app, S_IRGRP);
-        print_writable(app, S_IWGRP);
-        print_executable(app, S_IXGRP);
-
-        print_readable(app, S_IROTH);
-        print_writable(app, S_IWOTH);
-        print_executable(app, S_IXOTH);
+        let classes = [
+            (S_IRUSR, S_IWUSR, S_IXUSR),
+            (S_IRGRP, S_IWGRP, S_IXGRP),
+            (S_IROTH, S_IWOTH, S_IXOTH),
+        ];
+        for (class, (read, write, execute)) in classes.iter().enumerate() {
+            print_permission(app, *read, theme.read[class], "r");
+            print_permission(app, *write, theme.write[class], "w");
+            print_permission(app, *execute, theme.execute[class], "x");
+        }
 
         app.out
             .push(b' ')
-            .style(Style::White)
+            .style(theme.links)
             .align_right(status.links as usize, largest_links);
 
-        let get_or_default = |container: &[(libc::id_t, Vec<u8>)], key| {
-            container
-                .iter()
-                .find(|&it| it.0 == key)
-                .map(|v| v.1.clone())
-                .unwrap_or_default()
-        };
-
+        // Like GNU ls, ids without a name are lined up on the right like other numbers
         if app.print_owner {
-            let name = get_or_default(&app.uid_names, status.uid);
-            app.out
-                .push(b' ')
-                .style(YellowBold)
-                .align_left(&name, longest_name_len);
+            let style = if app.ids.is_me(status.uid) {
+                theme.user_self
+            } else {
+                theme.user_other
+            };
+            let name = if app.convert_id_to_name {
+                app.ids.user(status.uid)
+            } else {
+                None
+            };
+            app.out.push(b' ').style(style);
+            match name {
+                Some(name) => app.out.align_left(name, longest_name_len),
+                None => app.out.align_right(status.uid as usize, longest_name_len),
+            };
         }
 
         if app.print_group {
-            let group = get_or_default(&app.gid_names, status.gid);
-            app.out
-                .push(b' ')
-                .style(Style::YellowBold)
-                .align_left(&group, longest_group_len);
+            let style = if app.ids.is_my_group(status.gid) {
+                theme.group_self
+            } else {
+                theme.group_other
+            };
+            let group = if app.convert_id_to_name {
+                app.ids.group(status.gid)
+            } else {
+                None
+            };
+            app.out.push(b' ').style(style);
+            match group {
+                Some(group) => app.out.align_left(group, longest_group_len),
+                None => app.out.align_right(status.gid as usize, longest_group_len),
+            };
         }
 
         app.out
             .push(b' ')
-            .style(Style::GreenBold)
+            .style(theme.size_style(status.size))
             .align_right(status.size as usize, largest_size);
 
-        let localtime = unsafe {
-            let mut localtime = core::mem::zeroed();
-            libc::localtime_r(&status.time, &mut localtime);
-            localtime
-        };
+        let age = current_time - status.time;
+        let date_style = theme.date_style(age);
+        if app.time_style != TimeStyle::Locale {
+            print!(app, " ", date_style);
+            write_relative_time(&mut app.out, age, app.time_style == TimeStyle::Compact);
+        } else {
+            let localtime = unsafe {
+                let mut localtime = core::mem::zeroed();
+                libc::localtime_r(&status.time, &mut localtime);
+                localtime
+            };
 
-        print!(app, " ", Style::Blue, month_abbr(localtime.tm_mon), " ");
+            print!(app, " ", date_style, month_abbr(localtime.tm_mon), " ");
 
-        let day = localtime.tm_mday;
-        print!(app, (day < 10).map(" "), day, " ");
+            let day = localtime.tm_mday;
+            print!(app, (day < 10).map(" "), day, " ");
 
-        if current_time - status.time < one_year / 2 {
-            let hour = localtime.tm_hour;
-            print!(app, (hour < 10).map("0"), hour, ":");
+            if age < one_year / 2 {
+                let hour = localtime.tm_hour;
+                print!(app, (hour < 10).map("0"), hour, ":");
 
-            let minute = localtime.tm_min;
-            print!(app, (minute < 10).map("0"), minute);
-        } else {
-            print!(app, " ", localtime.tm_year + 1900);
+                let minute = localtime.tm_min;
+                print!(app, (minute < 10).map("0"), minute);
+            } else {
+                print!(app, " ", localtime.tm_year + 1900);
+            }
+        }
+
+        if let Some(repo) = app.git.as_mut() {
+            let git = repo.status(e.name().as_bytes(), status, app.ignore.as_mut());
+            print!(app, " ", git.staged, git.unstaged);
         }
 
         app.out.push(b' ');
 
-        let (style, suffix) = direntry.style(dir, app);
-        print!(app, style, e.name(), suffix.map(|s| (Style::White, s)));
+        let (style, suffix) = entry_style(direntry, dir, app);
+        app.out.style(style);
+        write_recorded_name(app, e.name(), false);
+        print!(app, suffix.map(|s| (Style::White, s)));
 
         if (mode & libc::S_IFMT) == libc::S_IFLNK {
             let mut buf = [0u8; 1024];
             if let Ok(linked_to) = veneer::syscalls::readlinkat(dir.raw_fd(), e.name(), &mut buf) {
-                print!(app, Style::Gray, " -> ", Style::White, linked_to);
+                print!(app, theme.symlink_arrow, " -> ", Style::White, linked_to);
             }
         }
 
@@ -231,6 +339,65 @@ pub fn write_details<T: DirEntry>(entries: &[(T, Status)], dir: &veneer::Directo
     }
 }
 
+/// Writes `age` seconds as "5 minutes ago", or "in 5 minutes" when negative, right-aligned so the
+/// column only grows for times a hundred years away. `compact` shortens that to "5m".
+fn write_relative_time(out: &mut BufferedStdout, age: libc::time_t, compact: bool) {
+    const MINUTE: libc::time_t = 60;
+    const HOUR: libc::time_t = 60 * MINUTE;
+    const DAY: libc::time_t = 24 * HOUR;
+    const UNITS: [(libc::time_t, &str, &str); 6] = [
+        (1, "s", " second"),
+        (MINUTE, "m", " minute"),
+        (HOUR, "h", " hour"),
+        (DAY, "d", " day"),
+        (30 * DAY, "mo", " month"),
+        (365 * DAY, "y", " year"),
+    ];
+
+    // Wide enough for "59 seconds ago" or "in 11mo"
+    let column = if compact { 7 } else { 14 };
+    if age == 0 {
+        out.pad(column - 3).write(b"now");
+        return;
+    }
+
+    let seconds = age.abs();
+    let (size, short, long) = UNITS
+        .iter()
+        .rev()
+        .find(|(size, ..)| seconds >= *size)
+        .copied()
+        .unwrap_or(UNITS[0]);
+    let count = seconds / size;
+
+    let mut buf = itoa::Buffer::new();
+    let number = buf.format(count).as_bytes();
+    let unit = if compact { short } else { long }.as_bytes();
+    let plural: &[u8] = if compact || count == 1 { b"" } else { b"s" };
+    let (prefix, suffix): (&[u8], &[u8]) = match (age < 0, compact) {
+        (true, _) => (b"in ", b""),
+        (false, true) => (b"", b""),
+        (false, false) => (b"", b" ago"),
+    };
+    let width = prefix.len() + number.len() + unit.len() + plural.len() + suffix.len();
+    out.pad(column.saturating_sub(width))
+        .write(prefix)
+        .write(number)
+        .write(unit)
+        .write(plural)
+        .write(suffix);
+}
+
+fn entry_style<T: DirEntry>(e: &T, dir: &crate::fs::Directory, app: &App) -> (Style, Option<u8>) {
+    let (style, suffix) = e.style(dir, app);
+    match &app.ignore {
+        Some(ignore) if app.color != Color::Never && ignore.is_dimmed(e.name().as_bytes()) => {
+            (Style::Gray, suffix)
+        }
+        _ => (style, suffix),
+    }
+}
+
 fn print_total_blocks<T: DirEntry>(entries: &[T], app: &mut App) {
     if app.display_size_in_blocks {
         print!(
@@ -244,7 +411,7 @@ fn print_total_blocks<T: DirEntry>(entries: &[T], app: &mut App) {
 
 pub fn write_grid<T: DirEntry>(
     entries: &[T],
-    dir: &veneer::Directory,
+    dir: &crate::fs::Directory,
     app: &mut App,
     terminal_width: usize,
 ) {
@@ -272,46 +439,47 @@ pub fn write_grid<T: DirEntry>(
     let mut styles = Vec::with_capacity(entries.len());
 
     for e in entries {
-        let style = e.style(dir, app);
+        let style = entry_style(e, dir, app);
         let len =
             len_utf8(e.name().as_bytes()) + style.1.is_some() as usize + inode_len + blocks_len;
         lengths.push(len);
         styles.push(style);
     }
 
-    let mut rows = entries.len();
-    for num_rows in (1..entries.len()).rev() {
-        let mut width_of_this_layout = 0;
-
-        // Add on the width of each column
-        for column in lengths.chunks(num_rows) {
-            width_of_this_layout += column.iter().max().copied().unwrap_or(1) + 2;
-        }
-        width_of_this_layout = width_of_this_layout.saturating_sub(2);
+    let across = app.grid_sort_direction == SortDirection::Horizontal;
 
-        if width_of_this_layout <= terminal_width {
-            rows = num_rows;
-        } else {
-            break;
-        }
+    // Like GNU ls, try every number of columns that could fit and take the most that do. Fewer
+    // columns can need more width than more of them, so the search cannot stop at the first miss.
+    // A layout must leave the last character of the line free, and the last column counts as at
+    // least MIN_COLUMN_WIDTH wide even though it has no gap after it.
+    let max_columns = (terminal_width / MIN_COLUMN_WIDTH).clamp(1, entries.len());
+    let mut widths = (2..=max_columns)
+        .rev()
+        .map(|columns| column_widths(&lengths, columns, across))
+        .find(|widths| match widths.split_last() {
+            Some((last, rest)) => {
+                rest.iter().sum::<usize>() + (last - 2).max(MIN_COLUMN_WIDTH) < terminal_width
+            }
+            None => false,
+        })
+        .unwrap_or_else(|| column_widths(&lengths, 1, across));
+    if let Some(w) = widths.last_mut() {
+        *w -= 2;
     }
-
-    let mut widths = lengths
-        .chunks(rows)
-        .map(|column| column.iter().max().copied().unwrap_or(1) + 2)
-        .collect::<Vec<_>>();
-    widths.last_mut().map(|w| *w -= 2);
+    let rows = entries.len().div_ceil(widths.len());
 
     for r in 0..rows {
         for (c, width) in widths.iter().enumerate() {
-            let (e, name_len, (style, suffix)) = match (
-                entries.get(c * rows + r),
-                lengths.get(c * rows + r),
-                styles.get(c * rows + r),
-            ) {
-                (Some(e), Some(name_len), Some(style)) => (e, name_len, style),
-                _ => continue,
+            let i = if across {
+                r * widths.len() + c
+            } else {
+                c * rows + r
             };
+            let (e, name_len, (style, suffix)) =
+                match (entries.get(i), lengths.get(i), styles.get(i)) {
+                    (Some(e), Some(name_len), Some(style)) => (e, name_len, style),
+                    _ => continue,
+                };
 
             if app.print_inode {
                 app.out
@@ -329,15 +497,37 @@ pub fn write_grid<T: DirEntry>(
 
             print!(app, style, e.name(), suffix.map(|s| (Style::White, s)));
 
-            for _ in 0..(width - name_len) {
-                app.out.push(b' ');
+            // Nothing pads the last entry of a row
+            let next = if across { i + 1 } else { i + rows };
+            if c + 1 < widths.len() && next < entries.len() {
+                app.out.pad(width - name_len);
             }
         }
         app.out.style(Style::Reset).push(b'\n');
     }
 }
 
-pub fn write_stream<T: DirEntry>(entries: &[T], dir: &veneer::Directory, app: &mut App) {
+/// Narrowest a column can be: one character and the gap after it
+const MIN_COLUMN_WIDTH: usize = 3;
+
+/// Width of each column plus the gap after it when `lengths` are laid out in at most `columns`
+/// columns, filling either down each column or across each row
+fn column_widths(lengths: &[usize], columns: usize, across: bool) -> Vec<usize> {
+    if across {
+        let mut widths = alloc::vec![0; columns];
+        for (i, len) in lengths.iter().enumerate() {
+            widths[i % columns] = widths[i % columns].max(len + 2);
+        }
+        widths
+    } else {
+        lengths
+            .chunks(lengths.len().div_ceil(columns))
+            .map(|column| column.iter().max().copied().unwrap_or(1) + 2)
+            .collect()
+    }
+}
+
+pub fn write_stream<T: DirEntry>(entries: &[T], dir: &crate::fs::Directory, app: &mut App) {
     print_total_blocks(entries, app);
 
     for e in entries.iter().take(entries.len() - 1) {
@@ -349,7 +539,7 @@ pub fn write_stream<T: DirEntry>(entries: &[T], dir: &veneer::Directory, app: &m
             print!(app, Style::White, e.blocks(), " ");
         }
 
-        let (style, suffix) = e.style(dir, app);
+        let (style, suffix) = entry_style(e, dir, app);
         print!(
             app,
             style,
@@ -365,7 +555,7 @@ pub fn write_stream<T: DirEntry>(entries: &[T], dir: &veneer::Directory, app: &m
     app.out.push(b'\n');
 }
 
-pub fn write_single_column<T: DirEntry>(entries: &[T], dir: &veneer::Directory, app: &mut App) {
+pub fn write_single_column<T: DirEntry>(entries: &[T], dir: &crate::fs::Directory, app: &mut App) {
     print_total_blocks(entries, app);
     let inode_len = if app.print_inode {
         let inode = entries.iter().map(DirEntry::inode).max().unwrap_or(0);
@@ -396,7 +586,7 @@ pub fn write_single_column<T: DirEntry>(entries: &[T], dir: &veneer::Directory,
                 .push(b' ');
         }
 
-        let (style, suffix) = e.style(dir, app);
+        let (style, suffix) = entry_style(e, dir, app);
         print!(
             app,
             style,
@@ -465,6 +655,46 @@ impl Writable for crate::Style {
     }
 }
 
+impl Writable for crate::git::Change {
+    fn write(&self, out: &mut BufferedStdout) {
+        use crate::git::Change::*;
+        let (style, letter) = match self {
+            Unchanged => (Style::Gray, b'-'),
+            Ignored => (Style::Gray, b'I'),
+            New => (Style::GreenBold, b'N'),
+            Modified => (Style::BlueBold, b'M'),
+            Conflicted => (Style::RedBold, b'U'),
+        };
+        out.style(style).push(letter);
+    }
+}
+
+impl Writable for crate::git::Head {
+    fn write(&self, out: &mut BufferedStdout) {
+        use crate::git::Branch;
+        match &self.branch {
+            Branch::Named(name) => {
+                out.style(Style::Gray)
+                    .write(b"On branch ")
+                    .style(Style::GreenBold)
+                    .write(name);
+            }
+            Branch::Detached(id) => {
+                out.style(Style::Gray)
+                    .write(b"HEAD detached at ")
+                    .style(Style::YellowBold)
+                    .write(id);
+            }
+        }
+        if let Some(operation) = self.operation {
+            out.style(Style::Gray)
+                .write(b", ")
+                .style(Style::RedBold)
+                .write(operation);
+        }
+    }
+}
+
 impl Writable for u8 {
     fn write(&self, out: &mut BufferedStdout) {
         out.push(*self);
@@ -515,28 +745,39 @@ where
 }
 
 pub struct BufferedStdout {
-    buf: [u8; 4096],
+    buf: Vec<u8>,
     buf_used: usize,
+    /// Number of bytes already written to stdout
+    flushed: usize,
     style: Style,
     is_terminal: bool,
+    /// Whether styles are written, which --color decides independently of where output goes
+    styled: bool,
+    depth: Depth,
 }
 
+const SPACES: [u8; 64] = [b' '; 64];
+
 impl BufferedStdout {
+    /// A terminal shows output as it arrives, so the buffer stays small
     pub fn terminal() -> Self {
-        Self {
-            buf: [0u8; 4096],
-            buf_used: 0,
-            style: Style::Reset,
-            is_terminal: true,
-        }
+        Self::with_capacity(8 * 1024, true)
     }
 
+    /// Files and pipes only care about throughput
     pub fn file() -> Self {
+        Self::with_capacity(64 * 1024, false)
+    }
+
+    fn with_capacity(capacity: usize, is_terminal: bool) -> Self {
         Self {
-            buf: [0u8; 4096],
+            buf: alloc::vec![0u8; capacity],
             buf_used: 0,
+            flushed: 0,
             style: Style::Reset,
-            is_terminal: false,
+            is_terminal,
+            styled: is_terminal,
+            depth: Depth::Eight,
         }
     }
 
@@ -544,28 +785,66 @@ impl BufferedStdout {
         self.is_terminal
     }
 
+    pub fn set_styled(&mut self, styled: bool) {
+        self.styled = styled;
+    }
+
+    /// Colours beyond what `depth` allows are written as the nearest one it has
+    pub fn set_depth(&mut self, depth: Depth) {
+        self.depth = depth;
+    }
+
+    /// Offset of the next byte from the start of the output
+    pub fn position(&self) -> usize {
+        self.flushed + self.buf_used
+    }
+
+    fn flush(&mut self) {
+        write_to_stdout([&self.buf[..self.buf_used]]);
+        self.flushed += self.buf_used;
+        self.buf_used = 0;
+    }
+
     pub fn push(&mut self, b: u8) -> &mut Self {
-        if let Some(out) = self.buf.get_mut(self.buf_used) {
-            *out = b;
-            self.buf_used += 1;
-        } else {
-            write_to_stdout(&self.buf);
-            self.buf_used = 1;
-            self.buf[0] = b;
+        if self.buf_used == self.buf.len() {
+            self.flush();
         }
+        self.buf[self.buf_used] = b;
+        self.buf_used += 1;
         self
     }
 
     pub fn write(&mut self, bytes: &[u8]) -> &mut Self {
-        for b in bytes {
-            self.push(*b);
+        if bytes.len() <= self.buf.len() - self.buf_used {
+            self.buf[self.buf_used..self.buf_used + bytes.len()].copy_from_slice(bytes);
+            self.buf_used += bytes.len();
+        } else if bytes.len() >= self.buf.len() {
+            // Copying something this large would only fill the buffer again, so write it out
+            // along with what is already buffered
+            write_to_stdout([&self.buf[..self.buf_used], bytes]);
+            self.flushed += self.buf_used + bytes.len();
+            self.buf_used = 0;
+        } else {
+            self.flush();
+            self.buf[..bytes.len()].copy_from_slice(bytes);
+            self.buf_used = bytes.len();
+        }
+        self
+    }
+
+    /// Writes `count` spaces
+    pub fn pad(&mut self, mut count: usize) -> &mut Self {
+        while count > 0 {
+            let n = count.min(SPACES.len());
+            self.write(&SPACES[..n]);
+            count -= n;
         }
         self
     }
 
     pub fn style(&mut self, style: Style) -> &mut Self {
-        if self.is_terminal && self.style != style {
-            self.write(style.to_bytes());
+        if self.styled && self.style != style {
+            self.write(style.escape(self.depth).as_bytes());
             self.style = style;
         }
         self
@@ -573,46 +852,66 @@ impl BufferedStdout {
 
     pub fn align_left(&mut self, value: &[u8], width: usize) -> &mut Self {
         self.write(value);
-        if value.len() < width {
-            for _ in 0..width - value.len() {
-                self.push(b' ');
-            }
-        }
-        self
+        self.pad(width.saturating_sub(value.len()))
     }
 
     pub fn align_right(&mut self, value: usize, width: usize) -> &mut Self {
         let mut buf = itoa::Buffer::new();
         let formatted = buf.format(value);
-        if formatted.len() < width {
-            for _ in 0..width - formatted.len() {
-                self.push(b' ');
-            }
-        }
-        self.write(formatted.as_bytes());
-        self
+        self.pad(width.saturating_sub(formatted.len()));
+        self.write(formatted.as_bytes())
     }
 }
 
 impl Drop for BufferedStdout {
     fn drop(&mut self) {
         self.style(Style::Reset);
-        // Panicking in a Drop is probably a bad idea, so we prefer to be slightly wrong
-        // in the case that our index has become invalid
-        if let Some(buf) = self.buf.get(..self.buf_used) {
-            write_to_stdout(buf);
-        }
+        self.flush();
     }
 }
 
-fn write_to_stdout(bytes: &[u8]) {
-    let mut bytes_written = 0;
-    while bytes_written < bytes.len() {
-        bytes_written += syscalls::write(libc::STDOUT_FILENO, &bytes[bytes_written..])
-            .unwrap_or_else(|_| {
-                syscalls::exit(-1);
-                0
-            });
+/// Writes all of `parts` to stdout in as few syscalls as possible, exiting if stdout is gone
+fn write_to_stdout<const N: usize>(parts: [&[u8]; N]) {
+    let mut iovecs = [libc::iovec {
+        iov_base: core::ptr::null_mut(),
+        iov_len: 0,
+    }; N];
+    let mut count = 0;
+    for part in parts.iter().filter(|part| !part.is_empty()) {
+        iovecs[count] = libc::iovec {
+            iov_base: part.as_ptr() as *mut libc::c_void,
+            iov_len: part.len(),
+        };
+        count += 1;
+    }
+    let mut remaining = &mut iovecs[..count];
+    while !remaining.is_empty() {
+        let written = unsafe {
+            libc::writev(
+                libc::STDOUT_FILENO,
+                remaining.as_ptr(),
+                remaining.len() as libc::c_int,
+            )
+        };
+        if written < 0 {
+            if unsafe { *libc::__errno_location() } == libc::EINTR {
+                continue;
+            }
+            syscalls::exit(-1);
+            return;
+        }
+
+        // Skip past whatever was written, which can end partway through an iovec
+        let mut written = written as usize;
+        while let Some(first) = remaining.first_mut() {
+            if written < first.iov_len {
+                first.iov_base = unsafe { (first.iov_base as *mut u8).add(written) } as *mut _;
+                first.iov_len -= written;
+                break;
+            }
+            written -= first.iov_len;
+            remaining = &mut remaining[1..];
+        }
     }
 }
 
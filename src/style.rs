@@ -17,12 +17,35 @@ pub enum Style {
     MagentaBold,
     CyanBold,
     WhiteBold,
+    /// Any colour, written as the nearest one the terminal can show
+    Custom {
+        color: Foreground,
+        bold: bool,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Foreground {
+    /// One of the 8 basic colours or their 8 bright versions
+    Ansi(u8),
+    /// One of the 256 colours of the xterm palette
+    Fixed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// How many colours the terminal can show, fewest first
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Depth {
+    Eight,
+    Sixteen,
+    Palette,
+    TrueColor,
 }
 
 impl Style {
-    pub fn to_bytes(self) -> &'static [u8] {
+    pub fn escape(self, depth: Depth) -> Escape {
         use Style::*;
-        match self {
+        let fixed: &[u8] = match self {
             Reset => b"\x1B[m",
             Red => b"\x1B[0;31m",
             Green => b"\x1B[0;32m",
@@ -31,7 +54,6 @@ impl Style {
             Magenta => b"\x1B[0;35m",
             Cyan => b"\x1B[0;36m",
             White => b"\x1B[0;37m",
-            Gray => b"\x1B[0;38;5;244m",
             RedBold => b"\x1B[1;31m",
             GreenBold => b"\x1B[1;32m",
             YellowBold => b"\x1B[1;33m",
@@ -39,6 +61,207 @@ impl Style {
             MagentaBold => b"\x1B[1;35m",
             CyanBold => b"\x1B[1;36m",
             WhiteBold => b"\x1B[1;37m",
+            Gray => {
+                let gray = Custom {
+                    color: Foreground::Fixed(244),
+                    bold: false,
+                };
+                return gray.escape(depth);
+            }
+            Custom { color, bold } => {
+                let color = color.for_depth(depth);
+                // Eight colour terminals have no gray, but most show bold black as one
+                let bold = bold || (depth == Depth::Eight && color == Foreground::Ansi(8));
+                let mut escape = Escape::new();
+                escape.push(if bold { b"\x1B[1;" } else { b"\x1B[0;" });
+                match color {
+                    Foreground::Ansi(n) if n < 8 || depth == Depth::Eight => {
+                        escape.number(30 + (n & 7))
+                    }
+                    Foreground::Ansi(n) => escape.number(90 + (n & 7)),
+                    Foreground::Fixed(n) => {
+                        escape.push(b"38;5;");
+                        escape.number(n);
+                    }
+                    Foreground::Rgb(r, g, b) => {
+                        escape.push(b"38;2;");
+                        escape.number(r);
+                        escape.push(b";");
+                        escape.number(g);
+                        escape.push(b";");
+                        escape.number(b);
+                    }
+                }
+                escape.push(b"m");
+                return escape;
+            }
+        };
+        let mut escape = Escape::new();
+        escape.push(fixed);
+        escape
+    }
+}
+
+/// The 16 basic colours as xterm shows them by default
+const ANSI: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Channel values of the 6x6x6 cube that makes up most of the 256 colour palette
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl Foreground {
+    /// The nearest colour a terminal of `depth` can show
+    fn for_depth(self, depth: Depth) -> Self {
+        use Foreground::*;
+        match (self, depth) {
+            (Ansi(n), Depth::Eight) if n >= 8 => self.nearest_ansi(false),
+            (Ansi(_), _) | (Fixed(_), Depth::Palette) | (_, Depth::TrueColor) => self,
+            (Rgb(r, g, b), Depth::Palette) => Fixed(nearest_fixed(r, g, b)),
+            (_, Depth::Sixteen) => self.nearest_ansi(true),
+            (_, Depth::Eight) => self.nearest_ansi(false),
         }
     }
+
+    /// Nearest by RGB distance turns dark colours black and grays yellow, so the basic colours
+    /// are picked by hue instead: channels above the midway point say which one. Colours that are
+    /// nearly gray pick black, gray or white by their brightness, where gray is bright black even
+    /// with eight colours.
+    fn nearest_ansi(self, bright: bool) -> Self {
+        let (r, g, b) = self.rgb();
+        let (max, min) = (r.max(g).max(b) as u32, r.min(g).min(b) as u32);
+        if (max - min) * 4 < max {
+            let luma = (r as u32 * 3 + g as u32 * 6 + b as u32) / 10;
+            return Foreground::Ansi(match luma {
+                0..=63 => 0,
+                64..=159 => 8,
+                160..=223 if bright => 7,
+                _ if bright => 15,
+                _ => 7,
+            });
+        }
+        let mid = (max + min) / 2;
+        let above = |c: u8| (c as u32 > mid) as u8;
+        let hue = above(r) | above(g) << 1 | above(b) << 2;
+        Foreground::Ansi(if bright && max > 230 { hue + 8 } else { hue })
+    }
+
+    fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Foreground::Ansi(n) => ANSI[(n & 15) as usize],
+            Foreground::Fixed(n) if n < 16 => ANSI[n as usize],
+            Foreground::Fixed(n) if n < 232 => {
+                let n = n - 16;
+                (
+                    CUBE[(n / 36) as usize],
+                    CUBE[(n / 6 % 6) as usize],
+                    CUBE[(n % 6) as usize],
+                )
+            }
+            Foreground::Fixed(n) => {
+                let level = 8 + (n - 232) * 10;
+                (level, level, level)
+            }
+            Foreground::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+}
+
+/// The closest entry in the cube or the gray ramp of the 256 colour palette
+fn nearest_fixed(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8| {
+        (0..6)
+            .min_by_key(|i| (CUBE[*i] as i32 - c as i32).abs())
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * level(r) + 6 * level(g) + level(b);
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray = 232 + (average.saturating_sub(3) / 10).min(23) as u8;
+    let fixed = |n: u8| distance(Foreground::Fixed(n).rgb(), (r, g, b));
+    if fixed(gray) < fixed(cube) {
+        gray
+    } else {
+        cube
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let channel = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    channel(a.0, b.0) + channel(a.1, b.1) + channel(a.2, b.2)
+}
+
+impl Depth {
+    /// Trusts COLORTERM, then the terminfo entry for TERM, then guesses from the name in TERM
+    pub fn detect() -> Self {
+        use crate::fs::getenv;
+        if let Some(b"truecolor") | Some(b"24bit") = getenv(b"COLORTERM\0") {
+            return Depth::TrueColor;
+        }
+        let term = match getenv(b"TERM\0") {
+            Some(term) if !term.is_empty() && term != b"dumb" => term,
+            _ => return Depth::Eight,
+        };
+        if let Some(colors) = crate::terminfo::colors(term) {
+            return match colors {
+                c if c >= 1 << 24 => Depth::TrueColor,
+                c if c >= 256 => Depth::Palette,
+                c if c >= 16 => Depth::Sixteen,
+                _ => Depth::Eight,
+            };
+        }
+        let named = |part: &[u8]| term.windows(part.len()).any(|w| w == part);
+        if named(b"-direct") || named(b"truecolor") {
+            Depth::TrueColor
+        } else if named(b"256color") {
+            Depth::Palette
+        } else if named(b"16color") {
+            Depth::Sixteen
+        } else {
+            Depth::Eight
+        }
+    }
+}
+
+/// An escape sequence built without allocating, long enough for a bold RGB colour
+pub struct Escape {
+    bytes: [u8; 24],
+    len: usize,
+}
+
+impl Escape {
+    fn new() -> Self {
+        Escape {
+            bytes: [0; 24],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn number(&mut self, n: u8) {
+        let mut buf = itoa::Buffer::new();
+        self.push(buf.format(n).as_bytes());
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
 }
@@ -0,0 +1,89 @@
+//! Just enough of the compiled terminfo format to learn how many colours a terminal has
+
+use crate::fs;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+const LEGACY_MAGIC: i16 = 0o432;
+/// Same layout, but with 32-bit numbers so direct colour terminals can say they have 2^24 colours
+const EXTENDED_MAGIC: i16 = 0o1036;
+/// Position of `colors` among the numeric capabilities, which terminfo fixes for all time
+const COLORS: usize = 13;
+
+/// The `colors` capability of the terminal called `term`, if its entry can be found and has one
+pub fn colors(term: &[u8]) -> Option<u32> {
+    if term.is_empty() || term.contains(&b'/') || term.starts_with(b".") {
+        return None;
+    }
+    let first = term[0];
+    let hex = |n: u8| b"0123456789abcdef"[n as usize];
+    // Entries are filed under their first letter, or under its hex code on macOS
+    let subdirs: [&[u8]; 2] = [&[first], &[hex(first >> 4), hex(first & 0xf)]];
+    for dir in directories() {
+        for subdir in subdirs.iter() {
+            let path = [&dir[..], b"/", subdir, b"/", term].concat();
+            if let Some(entry) = fs::read(&path) {
+                return parse_colors(&entry);
+            }
+        }
+    }
+    None
+}
+
+/// Where ncurses looks for entries, in the order it looks
+fn directories() -> Vec<Vec<u8>> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = fs::getenv(b"TERMINFO\0") {
+        dirs.push(dir.to_vec());
+    }
+    if let Some(home) = fs::getenv(b"HOME\0") {
+        dirs.push([home, b"/.terminfo"].concat());
+    }
+    if let Some(list) = fs::getenv(b"TERMINFO_DIRS\0") {
+        dirs.extend(
+            list.split(|b| *b == b':')
+                .filter(|d| !d.is_empty())
+                .map(<[u8]>::to_vec),
+        );
+    }
+    for dir in &[
+        &b"/etc/terminfo"[..],
+        b"/lib/terminfo",
+        b"/usr/share/terminfo",
+    ] {
+        dirs.push(dir.to_vec());
+    }
+    dirs
+}
+
+/// Reads `colors` out of a compiled entry: a header of six little-endian shorts, the names, one
+/// byte per boolean, padding to an even offset, then the numbers
+fn parse_colors(entry: &[u8]) -> Option<u32> {
+    let short = |i: usize| {
+        let bytes = entry.get(i..i + 2)?;
+        Some(i16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let number_size = match short(0)? {
+        LEGACY_MAGIC => 2,
+        EXTENDED_MAGIC => 4,
+        _ => return None,
+    };
+    let names_size = usize::try_from(short(2)?).ok()?;
+    let bools = usize::try_from(short(4)?).ok()?;
+    let numbers = usize::try_from(short(6)?).ok()?;
+    if numbers <= COLORS {
+        return None;
+    }
+
+    let mut offset = 12 + names_size + bools;
+    offset += offset % 2;
+    let at = offset + COLORS * number_size;
+    let bytes = entry.get(at..at + number_size)?;
+    // Negative values mean the capability is absent or cancelled
+    let colors = if number_size == 2 {
+        i16::from_le_bytes([bytes[0], bytes[1]]) as i32
+    } else {
+        i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    };
+    u32::try_from(colors).ok().filter(|c| *c > 0)
+}
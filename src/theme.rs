@@ -0,0 +1,260 @@
+//! Styles of the columns of long listings. A preset is the starting point, then the theme file
+//! and FLS_COLORS can restyle any element with entries like `uu=1;33`, separated by colons or
+//! newlines. Values are SGR codes, as in EXA_COLORS and LS_COLORS.
+
+use crate::cli::config_file;
+use crate::diagnostics::quote;
+use crate::style::{Foreground, Style};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Dark,
+    Light,
+}
+
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub inode: Style,
+    pub blocks: Style,
+    /// Each permission has a style for the user, the group and others, in that order
+    pub read: [Style; 3],
+    pub write: [Style; 3],
+    pub execute: [Style; 3],
+    /// The `-` of a permission that is not granted
+    pub no_permission: Style,
+    pub links: Style,
+    /// Owners and groups that are the user running fls, or that it belongs to
+    pub user_self: Style,
+    pub user_other: Style,
+    pub group_self: Style,
+    pub group_other: Style,
+    pub size: Style,
+    pub date: Style,
+    pub symlink_arrow: Style,
+    /// Sizes under a KiB, a MiB and a GiB, and larger ones, for --color-scale
+    pub size_scale: [Style; 4],
+    /// Times within the last hour, day, week and month, and older ones, for --color-scale
+    pub age_scale: [Style; 5],
+    pub scale_sizes: bool,
+    pub scale_ages: bool,
+}
+
+impl Theme {
+    pub fn preset(preset: Preset) -> Self {
+        use Style::*;
+        match preset {
+            Preset::Dark => Theme {
+                inode: Magenta,
+                blocks: White,
+                read: [GreenBold; 3],
+                write: [YellowBold; 3],
+                execute: [RedBold; 3],
+                no_permission: Gray,
+                links: White,
+                user_self: YellowBold,
+                user_other: Yellow,
+                group_self: YellowBold,
+                group_other: Yellow,
+                size: GreenBold,
+                date: Blue,
+                symlink_arrow: Gray,
+                size_scale: [Green, GreenBold, YellowBold, RedBold],
+                age_scale: [CyanBold, Cyan, BlueBold, Blue, Gray],
+                scale_sizes: false,
+                scale_ages: false,
+            },
+            // White and yellow vanish on a light background
+            Preset::Light => {
+                let brown = |bold| Custom {
+                    color: Foreground::Fixed(94),
+                    bold,
+                };
+                Theme {
+                    inode: Magenta,
+                    blocks: Reset,
+                    read: [Green; 3],
+                    write: [brown(false); 3],
+                    execute: [Red; 3],
+                    no_permission: Gray,
+                    links: Reset,
+                    user_self: brown(true),
+                    user_other: brown(false),
+                    group_self: brown(true),
+                    group_other: brown(false),
+                    size: GreenBold,
+                    date: Blue,
+                    symlink_arrow: Gray,
+                    size_scale: [Green, GreenBold, brown(true), RedBold],
+                    age_scale: [MagentaBold, Magenta, BlueBold, Blue, Gray],
+                    scale_sizes: false,
+                    scale_ages: false,
+                }
+            }
+        }
+    }
+
+    /// The preset with the theme file, if `read_file`, and then FLS_COLORS applied to it
+    pub fn load(preset: Preset, read_file: bool) -> Self {
+        let mut theme = Theme::preset(preset);
+        if let (true, Some(path)) = (read_file, config_file(b"theme")) {
+            if let Some(contents) = crate::fs::read(&path) {
+                for (i, line) in contents.split(|b| *b == b'\n').enumerate() {
+                    if line.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'#') {
+                        continue;
+                    }
+                    let mut origin = path.clone();
+                    origin.push(b':');
+                    origin.extend_from_slice(itoa::Buffer::new().format(i + 1).as_bytes());
+                    origin.extend_from_slice(b": ");
+                    theme.apply(line, &origin);
+                }
+            }
+        }
+        if let Some(colors) = crate::fs::getenv(b"FLS_COLORS\0") {
+            theme.apply(colors, b"FLS_COLORS: ");
+        }
+        theme
+    }
+
+    pub fn size_style(&self, size: libc::off_t) -> Style {
+        if !self.scale_sizes {
+            return self.size;
+        }
+        let magnitude = match size {
+            s if s < 1 << 10 => 0,
+            s if s < 1 << 20 => 1,
+            s if s < 1 << 30 => 2,
+            _ => 3,
+        };
+        self.size_scale[magnitude]
+    }
+
+    /// Times in the future count as the most recent
+    pub fn date_style(&self, age: libc::time_t) -> Style {
+        if !self.scale_ages {
+            return self.date;
+        }
+        let hour = 60 * 60;
+        let bucket = match age {
+            a if a < hour => 0,
+            a if a < 24 * hour => 1,
+            a if a < 7 * 24 * hour => 2,
+            a if a < 30 * 24 * hour => 3,
+            _ => 4,
+        };
+        self.age_scale[bucket]
+    }
+
+    /// Applies colon-separated `key=value` entries. Mistakes are reported and skipped, since the
+    /// listing is still worth having in the wrong colours.
+    fn apply(&mut self, entries: &[u8], origin: &[u8]) {
+        for entry in entries.split(|b| *b == b':') {
+            let entry = entry.trim_ascii();
+            if entry.is_empty() {
+                continue;
+            }
+            let (key, value) = match entry.iter().position(|b| *b == b'=') {
+                Some(i) => (&entry[..i], &entry[i + 1..]),
+                None => {
+                    error!(
+                        origin,
+                        b"ignoring theme entry without a value: ",
+                        quote(entry)
+                    );
+                    continue;
+                }
+            };
+            match (self.element(key), parse_style(value)) {
+                (Some(element), Some(style)) => *element = style,
+                (None, _) => error!(origin, b"ignoring unknown theme element ", quote(key)),
+                (_, None) => error!(
+                    origin,
+                    b"ignoring invalid style for ",
+                    quote(key),
+                    b": ",
+                    quote(value)
+                ),
+            }
+        }
+    }
+
+    /// The style a theme key names. The keys are those of EXA_COLORS where it has one.
+    fn element(&mut self, key: &[u8]) -> Option<&mut Style> {
+        Some(match key {
+            b"in" => &mut self.inode,
+            b"bl" => &mut self.blocks,
+            b"ur" => &mut self.read[0],
+            b"uw" => &mut self.write[0],
+            b"ux" => &mut self.execute[0],
+            b"gr" => &mut self.read[1],
+            b"gw" => &mut self.write[1],
+            b"gx" => &mut self.execute[1],
+            b"tr" => &mut self.read[2],
+            b"tw" => &mut self.write[2],
+            b"tx" => &mut self.execute[2],
+            b"xx" => &mut self.no_permission,
+            b"lc" => &mut self.links,
+            b"uu" => &mut self.user_self,
+            b"un" => &mut self.user_other,
+            b"gu" => &mut self.group_self,
+            b"gn" => &mut self.group_other,
+            b"sn" => &mut self.size,
+            b"da" => &mut self.date,
+            b"la" => &mut self.symlink_arrow,
+            b"nb" => &mut self.size_scale[0],
+            b"nk" => &mut self.size_scale[1],
+            b"nm" => &mut self.size_scale[2],
+            b"ng" => &mut self.size_scale[3],
+            b"dh" => &mut self.age_scale[0],
+            b"dd" => &mut self.age_scale[1],
+            b"dw" => &mut self.age_scale[2],
+            b"dm" => &mut self.age_scale[3],
+            b"do" => &mut self.age_scale[4],
+            _ => return None,
+        })
+    }
+}
+
+/// Reads SGR codes such as `1;38;5;208`. Only boldness and the foreground colour can be shown, so
+/// other attributes are accepted and left out.
+fn parse_style(value: &[u8]) -> Option<Style> {
+    let mut codes = value.split(|b| *b == b';').map(parse_code);
+    let mut bold = false;
+    let mut color = None;
+    while let Some(code) = codes.next() {
+        match code? {
+            0 => {
+                bold = false;
+                color = None;
+            }
+            1 => bold = true,
+            code @ 30..=37 => color = Some(Foreground::Ansi(code - 30)),
+            code @ 90..=97 => color = Some(Foreground::Ansi(code - 90 + 8)),
+            38 => {
+                let mut next = || codes.next().flatten();
+                color = Some(match next()? {
+                    5 => Foreground::Fixed(next()?),
+                    2 => Foreground::Rgb(next()?, next()?, next()?),
+                    _ => return None,
+                });
+            }
+            _ => {}
+        }
+    }
+    Some(match color {
+        Some(color) => Style::Custom { color, bold },
+        None if bold => Style::WhiteBold,
+        None => Style::Reset,
+    })
+}
+
+/// An empty code means 0, as it does to terminals
+fn parse_code(code: &[u8]) -> Option<u8> {
+    code.iter().try_fold(0u8, |n, b| {
+        if b.is_ascii_digit() {
+            n.checked_mul(10)?.checked_add(b - b'0')
+        } else {
+            None
+        }
+    })
+}
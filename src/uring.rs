@@ -0,0 +1,357 @@
+//! Batched statx through io_uring, so the latency of a slow filesystem is paid once per batch
+//! instead of once per entry. libc does not know about io_uring yet, so the kernel ABI is spelled
+//! out here.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use veneer::{CStr, Error};
+
+// These are the same on every architecture
+const SYS_IO_URING_SETUP: libc::c_long = 425;
+const SYS_IO_URING_ENTER: libc::c_long = 426;
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x800_0000;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_STATX: u8 = 21;
+
+const STATX_BASIC_STATS: u32 = 0x7ff;
+
+const ENTRIES: u32 = 256;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    /// For statx this is the address of the result buffer
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    pad: [u64; 3],
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct StatxTimestamp {
+    tv_sec: i64,
+    tv_nsec: u32,
+    reserved: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Statx {
+    mask: u32,
+    blksize: u32,
+    attributes: u64,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    mode: u16,
+    spare0: u16,
+    ino: u64,
+    size: u64,
+    blocks: u64,
+    attributes_mask: u64,
+    atime: StatxTimestamp,
+    btime: StatxTimestamp,
+    ctime: StatxTimestamp,
+    mtime: StatxTimestamp,
+    rdev_major: u32,
+    rdev_minor: u32,
+    dev_major: u32,
+    dev_minor: u32,
+    spare2: [u64; 14],
+}
+
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: libc::c_int, len: usize, offset: libc::off_t) -> Option<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            None
+        } else {
+            Some(Mmap {
+                ptr: ptr as *mut u8,
+                len,
+            })
+        }
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+pub struct Ring {
+    fd: libc::c_int,
+    params: Params,
+    sq_ring: Mmap,
+    /// Only mapped separately on kernels without IORING_FEAT_SINGLE_MMAP
+    cq_ring: Option<Mmap>,
+    sqes: Mmap,
+}
+
+impl Ring {
+    /// Sets up a ring, or returns `None` if the kernel does not support io_uring or it is
+    /// disabled
+    pub fn new() -> Option<Self> {
+        let mut params = Params::default();
+        let fd = unsafe { libc::syscall(SYS_IO_URING_SETUP, ENTRIES, &mut params as *mut Params) };
+        if fd < 0 {
+            return None;
+        }
+        let fd = fd as libc::c_int;
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * core::mem::size_of::<Cqe>();
+        let single_mmap = params.features & IORING_FEAT_SINGLE_MMAP != 0;
+
+        let maps = (|| {
+            let sq_ring = Mmap::new(
+                fd,
+                if single_mmap {
+                    sq_len.max(cq_len)
+                } else {
+                    sq_len
+                },
+                IORING_OFF_SQ_RING,
+            )?;
+            let cq_ring = if single_mmap {
+                None
+            } else {
+                Some(Mmap::new(fd, cq_len, IORING_OFF_CQ_RING)?)
+            };
+            let sqes = Mmap::new(
+                fd,
+                params.sq_entries as usize * core::mem::size_of::<Sqe>(),
+                IORING_OFF_SQES,
+            )?;
+            Some((sq_ring, cq_ring, sqes))
+        })();
+
+        match maps {
+            Some((sq_ring, cq_ring, sqes)) => Some(Ring {
+                fd,
+                params,
+                sq_ring,
+                cq_ring,
+                sqes,
+            }),
+            None => {
+                unsafe { libc::close(fd) };
+                None
+            }
+        }
+    }
+
+    fn cq_ring(&self) -> &Mmap {
+        self.cq_ring.as_ref().unwrap_or(&self.sq_ring)
+    }
+
+    /// Stats every one of `names` in `dirfd`, following symlinks if `follow` is set. Returns
+    /// `None` if the kernel cannot do statx through io_uring, in which case the ring is of no
+    /// further use.
+    pub fn stat_all(
+        &mut self,
+        dirfd: libc::c_int,
+        names: &[CStr],
+        follow: bool,
+    ) -> Option<Vec<Result<libc::stat64, Error>>> {
+        let flags = if follow {
+            0
+        } else {
+            libc::AT_SYMLINK_NOFOLLOW as u32
+        };
+        let mut results = Vec::with_capacity(names.len());
+        let mut buffers: Vec<Statx> = Vec::new();
+        let mut codes: Vec<Option<i32>> = Vec::new();
+
+        for batch in names.chunks(self.params.sq_entries as usize) {
+            buffers.clear();
+            buffers.resize(batch.len(), unsafe { core::mem::zeroed() });
+            codes.clear();
+            codes.resize(batch.len(), None);
+
+            let sq_mask = unsafe { *self.sq_ring.at::<u32>(self.params.sq_off.ring_mask) };
+            let sq_tail = unsafe { &*self.sq_ring.at::<AtomicU32>(self.params.sq_off.tail) };
+            let array = self.sq_ring.at::<u32>(self.params.sq_off.array);
+            let mut tail = sq_tail.load(Ordering::Relaxed);
+            for (i, (name, buffer)) in batch.iter().zip(buffers.iter_mut()).enumerate() {
+                let index = tail & sq_mask;
+                unsafe {
+                    self.sqes.at::<Sqe>(0).add(index as usize).write(Sqe {
+                        opcode: IORING_OP_STATX,
+                        flags: 0,
+                        ioprio: 0,
+                        fd: dirfd,
+                        off: buffer as *mut Statx as u64,
+                        addr: name.as_bytes().as_ptr() as u64,
+                        len: STATX_BASIC_STATS,
+                        op_flags: flags,
+                        user_data: i as u64,
+                        pad: [0; 3],
+                    });
+                    *array.add(index as usize) = index;
+                }
+                tail = tail.wrapping_add(1);
+            }
+            sq_tail.store(tail, Ordering::Release);
+
+            let mut to_submit = batch.len() as u32;
+            let mut completed = 0;
+            while completed < batch.len() {
+                let submitted = unsafe {
+                    libc::syscall(
+                        SYS_IO_URING_ENTER,
+                        self.fd,
+                        to_submit,
+                        1,
+                        IORING_ENTER_GETEVENTS,
+                        core::ptr::null::<libc::c_void>(),
+                        0,
+                    )
+                };
+                if submitted < 0 {
+                    if unsafe { *libc::__errno_location() } == libc::EINTR {
+                        continue;
+                    }
+                    return None;
+                }
+                to_submit -= submitted as u32;
+
+                let cq = self.cq_ring();
+                let cq_mask = unsafe { *cq.at::<u32>(self.params.cq_off.ring_mask) };
+                let cq_head = unsafe { &*cq.at::<AtomicU32>(self.params.cq_off.head) };
+                let cq_tail = unsafe { &*cq.at::<AtomicU32>(self.params.cq_off.tail) };
+                let cqes = cq.at::<Cqe>(self.params.cq_off.cqes);
+                let mut head = cq_head.load(Ordering::Relaxed);
+                let tail = cq_tail.load(Ordering::Acquire);
+                while head != tail {
+                    let cqe = unsafe { &*cqes.add((head & cq_mask) as usize) };
+                    if let Some(code) = codes.get_mut(cqe.user_data as usize) {
+                        *code = Some(cqe.res);
+                    }
+                    head = head.wrapping_add(1);
+                    completed += 1;
+                }
+                cq_head.store(head, Ordering::Release);
+            }
+
+            for (code, buffer) in codes.iter().zip(buffers.iter()) {
+                match code {
+                    Some(0) => results.push(Ok(to_stat64(buffer))),
+                    // Kernels from before statx was added to io_uring reject the opcode itself
+                    Some(res) if *res == -libc::EINVAL => return None,
+                    Some(res) => results.push(Err(Error(-res as isize))),
+                    None => return None,
+                }
+            }
+        }
+
+        Some(results)
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn to_stat64(statx: &Statx) -> libc::stat64 {
+    let mut status: libc::stat64 = unsafe { core::mem::zeroed() };
+    status.st_dev = libc::makedev(statx.dev_major, statx.dev_minor);
+    status.st_ino = statx.ino as _;
+    status.st_nlink = statx.nlink as _;
+    status.st_mode = statx.mode as _;
+    status.st_uid = statx.uid;
+    status.st_gid = statx.gid;
+    status.st_rdev = libc::makedev(statx.rdev_major, statx.rdev_minor);
+    status.st_size = statx.size as _;
+    status.st_blksize = statx.blksize as _;
+    status.st_blocks = statx.blocks as _;
+    status.st_atime = statx.atime.tv_sec as _;
+    status.st_atime_nsec = statx.atime.tv_nsec as _;
+    status.st_mtime = statx.mtime.tv_sec as _;
+    status.st_mtime_nsec = statx.mtime.tv_nsec as _;
+    status.st_ctime = statx.ctime.tv_sec as _;
+    status.st_ctime_nsec = statx.ctime.tv_nsec as _;
+    status
+}
@@ -0,0 +1,297 @@
+//! Reads directories ahead of a recursive listing on a pool of threads.
+//!
+//! Output is still produced by the main thread in exactly the order of a sequential walk. Workers
+//! only open, read and optionally stat the directories the main thread is going to visit next,
+//! and hand the results over when it gets there. Jobs are keyed by their position in the walk, so
+//! idle workers always pick the directory that will be needed soonest.
+
+use crate::cli::ShowAll;
+use crate::fs;
+use alloc::{sync::Arc, vec::Vec};
+use core::cell::UnsafeCell;
+use veneer::{syscalls, CStr, Error};
+
+/// The most directories read ahead at once, across all levels of the walk. Each one holds a file
+/// descriptor until it is taken, so this is also kept well under the limit on open files.
+const BUDGET: usize = 256;
+
+/// Everything the main thread would otherwise have read from a directory itself
+pub struct Prefetched {
+    pub dir: fs::Directory,
+    pub listing: Listing,
+}
+
+pub struct Listing {
+    /// At most one window of entries, and whether that was all of them. The rest are left for the
+    /// main thread to read, so a huge directory can still be streamed.
+    pub contents: Result<(fs::Contents, bool), Error>,
+    /// Results of stat for each entry of `contents` that `ShowAll` lets through, in order, if
+    /// they were requested and the directory was read to the end
+    pub stats: Option<Vec<Result<libc::stat64, Error>>>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Options {
+    pub stat: bool,
+    pub follow_symlinks: bool,
+    pub show_all: ShowAll,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct JobId(u64);
+
+struct Job {
+    id: JobId,
+    /// Indices of the directories leading to this one, so comparing keys compares positions in
+    /// the walk
+    key: Vec<usize>,
+    /// An open directory, which stays open until the job has been taken
+    dirfd: libc::c_int,
+    /// Nul-terminated path of the directory relative to `dirfd`
+    path: Vec<u8>,
+    options: Options,
+}
+
+struct Queue {
+    pending: Vec<Job>,
+    finished: Vec<(JobId, Result<Prefetched, Error>)>,
+    shutdown: bool,
+}
+
+struct Shared {
+    lock: UnsafeCell<libc::pthread_mutex_t>,
+    work_ready: UnsafeCell<libc::pthread_cond_t>,
+    job_done: UnsafeCell<libc::pthread_cond_t>,
+    queue: UnsafeCell<Queue>,
+}
+
+// Everything but the pthread primitives is only touched while holding `lock`
+unsafe impl Sync for Shared {}
+unsafe impl Send for Shared {}
+
+struct Guard<'a> {
+    shared: &'a Shared,
+}
+
+impl Shared {
+    fn lock(&self) -> Guard<'_> {
+        unsafe { libc::pthread_mutex_lock(self.lock.get()) };
+        Guard { shared: self }
+    }
+}
+
+impl<'a> Guard<'a> {
+    fn queue(&mut self) -> &mut Queue {
+        unsafe { &mut *self.shared.queue.get() }
+    }
+
+    fn wait(&mut self, cond: &UnsafeCell<libc::pthread_cond_t>) {
+        unsafe { libc::pthread_cond_wait(cond.get(), self.shared.lock.get()) };
+    }
+}
+
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        unsafe { libc::pthread_mutex_unlock(self.shared.lock.get()) };
+    }
+}
+
+pub struct Walker {
+    shared: Arc<Shared>,
+    threads: Vec<libc::pthread_t>,
+    next_id: u64,
+    /// Position of the directory currently being listed
+    position: Vec<usize>,
+    /// Jobs submitted and not yet taken, and how many there may be
+    outstanding: usize,
+    budget: usize,
+}
+
+impl Walker {
+    /// Starts `threads` workers, or as many as there are online CPUs if it is 0. Returns `None`
+    /// if not a single thread could be started.
+    pub fn new(threads: usize) -> Option<Self> {
+        let threads = match threads {
+            0 => unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }.max(1) as usize,
+            n => n,
+        };
+
+        let shared = Arc::new(Shared {
+            lock: UnsafeCell::new(libc::PTHREAD_MUTEX_INITIALIZER),
+            work_ready: UnsafeCell::new(libc::PTHREAD_COND_INITIALIZER),
+            job_done: UnsafeCell::new(libc::PTHREAD_COND_INITIALIZER),
+            queue: UnsafeCell::new(Queue {
+                pending: Vec::new(),
+                finished: Vec::new(),
+                shutdown: false,
+            }),
+        });
+
+        let mut walker = Walker {
+            shared,
+            threads: Vec::with_capacity(threads),
+            next_id: 0,
+            position: Vec::new(),
+            outstanding: 0,
+            budget: open_files_limit().map_or(BUDGET, |limit| (limit / 4).clamp(1, BUDGET)),
+        };
+        for _ in 0..threads {
+            let arg = Arc::into_raw(walker.shared.clone()) as *mut libc::c_void;
+            let mut thread: libc::pthread_t = 0;
+            if unsafe { libc::pthread_create(&mut thread, core::ptr::null(), worker, arg) } != 0 {
+                drop(unsafe { Arc::from_raw(arg as *const Shared) });
+                break;
+            }
+            walker.threads.push(thread);
+        }
+
+        if walker.threads.is_empty() {
+            None
+        } else {
+            Some(walker)
+        }
+    }
+
+    /// Queues the `index`th subdirectory of the current directory, at `path` relative to `dirfd`,
+    /// to be read ahead. `dirfd` has to stay open until the job is taken. Returns `None` when as
+    /// many directories as the budget allows are already being read ahead, in which case the
+    /// caller has to open this one itself.
+    pub fn submit(
+        &mut self,
+        index: usize,
+        dirfd: libc::c_int,
+        path: CStr,
+        options: Options,
+    ) -> Option<JobId> {
+        if self.outstanding >= self.budget {
+            return None;
+        }
+        self.outstanding += 1;
+        let id = JobId(self.next_id);
+        self.next_id += 1;
+        let mut key = self.position.clone();
+        key.push(index);
+
+        let mut guard = self.shared.lock();
+        guard.queue().pending.push(Job {
+            id,
+            key,
+            dirfd,
+            path: fs::nul_terminated(path.as_bytes()),
+            options,
+        });
+        unsafe { libc::pthread_cond_signal(self.shared.work_ready.get()) };
+        Some(id)
+    }
+
+    /// Waits for a job submitted earlier. If no worker has started on it yet, it is run on this
+    /// thread instead. Fails if the directory could not be opened.
+    pub fn take(&mut self, id: JobId) -> Result<Prefetched, Error> {
+        self.outstanding -= 1;
+        let shared = self.shared.clone();
+        let mut guard = shared.lock();
+        loop {
+            let queue = guard.queue();
+            if let Some(i) = queue.finished.iter().position(|(done, _)| *done == id) {
+                return queue.finished.swap_remove(i).1;
+            }
+            if let Some(i) = queue.pending.iter().position(|job| job.id == id) {
+                let job = queue.pending.swap_remove(i);
+                drop(guard);
+                return job.run();
+            }
+            guard.wait(&shared.job_done);
+        }
+    }
+
+    /// Moves into the `index`th subdirectory of the current directory
+    pub fn descend(&mut self, index: usize) {
+        self.position.push(index);
+    }
+
+    pub fn ascend(&mut self) {
+        self.position.pop();
+    }
+}
+
+impl Drop for Walker {
+    fn drop(&mut self) {
+        {
+            let mut guard = self.shared.lock();
+            guard.queue().shutdown = true;
+            unsafe { libc::pthread_cond_broadcast(self.shared.work_ready.get()) };
+        }
+        for thread in self.threads.iter() {
+            unsafe { libc::pthread_join(*thread, core::ptr::null_mut()) };
+        }
+    }
+}
+
+/// The soft limit on open files, if there is one
+fn open_files_limit() -> Option<usize> {
+    let mut limit: libc::rlimit = unsafe { core::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+    Some(limit.rlim_cur as usize)
+}
+
+extern "C" fn worker(arg: *mut libc::c_void) -> *mut libc::c_void {
+    let shared = unsafe { Arc::from_raw(arg as *const Shared) };
+    loop {
+        let job = {
+            let mut guard = shared.lock();
+            loop {
+                let queue = guard.queue();
+                if queue.shutdown {
+                    return core::ptr::null_mut();
+                }
+                let soonest = queue
+                    .pending
+                    .iter()
+                    .enumerate()
+                    .min_by(|a, b| a.1.key.cmp(&b.1.key))
+                    .map(|(i, _)| i);
+                if let Some(i) = soonest {
+                    break queue.pending.swap_remove(i);
+                }
+                guard.wait(&shared.work_ready);
+            }
+        };
+
+        let result = job.run();
+        let mut guard = shared.lock();
+        guard.queue().finished.push((job.id, result));
+        unsafe { libc::pthread_cond_broadcast(shared.job_done.get()) };
+    }
+}
+
+impl Job {
+    fn run(&self) -> Result<Prefetched, Error> {
+        let path = CStr::from_bytes(&self.path);
+        let dir = fs::Directory::open_at(self.dirfd, path, self.options.follow_symlinks)?;
+        let contents = dir.read_some(crate::STREAM_WINDOW);
+        let stats = match (&contents, self.options.stat) {
+            (Ok((contents, true)), true) => Some(
+                contents
+                    .iter()
+                    .filter(|e| self.options.show_all.shows(e.name().as_bytes()))
+                    .map(|e| {
+                        if self.options.follow_symlinks {
+                            syscalls::fstatat(dir.raw_fd(), e.name())
+                        } else {
+                            syscalls::lstatat(dir.raw_fd(), e.name())
+                        }
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+        Ok(Prefetched {
+            dir,
+            listing: Listing { contents, stats },
+        })
+    }
+}
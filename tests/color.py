@@ -0,0 +1,74 @@
+import os
+import pty
+import subprocess
+
+from common import check, exe_location, finish, run, scratch
+
+
+def on_terminal(args, cwd, env):
+    """What fls prints when its output goes to a terminal"""
+    master, slave = pty.openpty()
+    subprocess.run([exe_location, *args], cwd=cwd, env=env, stdout=slave, check=True)
+    os.close(slave)
+    output = b''
+    try:
+        while True:
+            chunk = os.read(master, 65536)
+            if not chunk:
+                break
+            output += chunk
+    except OSError:
+        # Reading the master side fails once the terminal has been closed and drained
+        pass
+    os.close(master)
+    return output
+
+
+# Arguments, the environment variables set, then whether the output should be coloured through a
+# pipe and on a terminal
+cases = [
+    ([], {}, False, True),
+    (['--color=auto'], {}, False, True),
+    (['--color=always'], {}, True, True),
+    (['--color'], {}, True, True),
+    (['--color=never'], {}, False, False),
+    ([], {'NO_COLOR': '1'}, False, False),
+    ([], {'NO_COLOR': ''}, False, True),
+    ([], {'CLICOLOR': '0'}, False, False),
+    ([], {'CLICOLOR': '1'}, False, True),
+    ([], {'CLICOLOR_FORCE': '1'}, True, True),
+    ([], {'CLICOLOR_FORCE': '0'}, False, True),
+    ([], {'CLICOLOR_FORCE': '1', 'CLICOLOR': '0'}, True, True),
+    ([], {'CLICOLOR_FORCE': '1', 'NO_COLOR': '1'}, False, False),
+    # An explicit --color wins over all of them
+    (['--color=always'], {'NO_COLOR': '1'}, True, True),
+    (['--color=never'], {'CLICOLOR_FORCE': '1'}, False, False),
+    (['--color=auto'], {'CLICOLOR_FORCE': '1'}, False, True),
+    (['--color=auto'], {'NO_COLOR': '1'}, False, True),
+    # The cheaper colouring still colours, and only when colour is on
+    (['--fast-color'], {}, False, True),
+    (['--fast-color', '--color=always'], {}, True, True),
+    (['--fast-color', '--color=never'], {}, False, False),
+]
+
+with scratch() as (root, env):
+    os.mkdir(os.path.join(root, 'directory'))
+    open(os.path.join(root, 'file'), 'w').close()
+    os.symlink('file', os.path.join(root, 'link'))
+    env = dict(env, TERM='xterm')
+    for name in ['NO_COLOR', 'CLICOLOR', 'CLICOLOR_FORCE', 'COLORTERM', 'LS_COLORS']:
+        env.pop(name, None)
+
+    for args, variables, piped, terminal in cases:
+        what = ' '.join([*args, *('%s=%s' % v for v in variables.items())])
+        case_env = dict(env, **variables)
+        result = run(['-1', *args], root, case_env)
+        check(what + ' status', result.returncode, 0)
+        check(what + ' coloured through a pipe', b'\x1b[' in result.stdout, piped)
+        output = on_terminal(['-1', *args], root, case_env)
+        check(what + ' coloured on a terminal', b'\x1b[' in output, terminal)
+
+    # Without colour the names are all there is, wherever they go
+    check('plain listing', run(['-1'], root, env).stdout, b'directory\nfile\nlink\n')
+
+finish()
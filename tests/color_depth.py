@@ -0,0 +1,94 @@
+import os
+import struct
+
+from common import check, finish, run, scratch
+
+
+def terminfo_entry(names, colors, extended=False, bools=3):
+    """A compiled terminfo entry with nothing but `colors` set, in the legacy format or in the one
+    with 32-bit numbers"""
+    names = names.encode() + b'\0'
+    numbers = [-1] * 14
+    numbers[13] = colors
+    header = struct.pack('<6h', 0o1036 if extended else 0o432, len(names), bools, len(numbers), 0,
+                         0)
+    entry = header + names + b'\1' * bools
+    if len(entry) % 2:
+        entry += b'\0'
+    return entry + struct.pack('<%d%s' % (len(numbers), 'i' if extended else 'h'), *numbers)
+
+
+TRUECOLOR = '\x1b[0;38;2;255;128;0m'
+PALETTE = '\x1b[0;38;5;208m'
+SIXTEEN = '\x1b[0;93m'
+EIGHT = '\x1b[0;33m'
+
+# The variables set, then how an orange inode should be written
+cases = [
+    ({'COLORTERM': 'truecolor'}, TRUECOLOR),
+    ({'COLORTERM': '24bit'}, TRUECOLOR),
+    ({'COLORTERM': 'truecolor', 'TERM': 'eight'}, TRUECOLOR),
+    ({'COLORTERM': 'yes', 'TERM': 'unknown-256color'}, PALETTE),
+    ({}, EIGHT),
+    ({'TERM': ''}, EIGHT),
+    ({'TERM': 'dumb'}, EIGHT),
+    # Without a terminfo entry, the name says what it can
+    ({'TERM': 'unknown'}, EIGHT),
+    ({'TERM': 'unknown-16color'}, SIXTEEN),
+    ({'TERM': 'unknown-256color'}, PALETTE),
+    ({'TERM': 'unknown-direct'}, TRUECOLOR),
+    ({'TERM': 'unknown-truecolor'}, TRUECOLOR),
+    # An entry wins over the name
+    ({'TERM': 'eight'}, EIGHT),
+    ({'TERM': 'sixteen'}, SIXTEEN),
+    ({'TERM': 'palette'}, PALETTE),
+    ({'TERM': 'direct'}, TRUECOLOR),
+    ({'TERM': 'liar-256color'}, EIGHT),
+    ({'TERM': 'hexadecimal'}, PALETTE),
+    ({'TERM': 'elsewhere', 'TERMINFO': 'terminfo'}, SIXTEEN),
+    ({'TERM': 'elsewhere', 'TERMINFO_DIRS': 'nowhere::terminfo'}, SIXTEEN),
+    # An entry without colours says nothing, and names that would leave the directory are not
+    # looked up
+    ({'TERM': 'colorless-256color'}, PALETTE),
+    ({'TERM': '../h/hexadecimal'}, EIGHT),
+]
+
+with scratch() as (root, env):
+    for name in ['COLORTERM', 'TERM', 'TERMINFO', 'TERMINFO_DIRS', 'NO_COLOR']:
+        env.pop(name, None)
+    entries = [
+        ('.terminfo/e/eight', terminfo_entry('eight', 8)),
+        ('.terminfo/s/sixteen', terminfo_entry('sixteen|sixteen colours', 16, bools=4)),
+        ('.terminfo/p/palette', terminfo_entry('palette', 256)),
+        ('.terminfo/d/direct', terminfo_entry('direct', 1 << 24, extended=True)),
+        ('.terminfo/l/liar-256color', terminfo_entry('liar-256color', 8)),
+        ('.terminfo/c/colorless-256color', terminfo_entry('colorless-256color', -1)),
+        # Where macOS files entries
+        ('.terminfo/68/hexadecimal', terminfo_entry('hexadecimal', 256)),
+        ('terminfo/e/elsewhere', terminfo_entry('elsewhere', 16)),
+    ]
+    for path, entry in entries:
+        os.makedirs(os.path.dirname(os.path.join(root, path)), exist_ok=True)
+        with open(os.path.join(root, path), 'wb') as f:
+            f.write(entry)
+    open(os.path.join(root, 'file'), 'w').close()
+    inode = str(os.stat(os.path.join(root, 'file')).st_ino)
+    env = dict(env, FLS_COLORS='in=38;2;255;128;0')
+
+    for variables, expected in cases:
+        what = ' '.join('%s=%s' % v for v in variables.items()) or 'nothing'
+        result = run(['-li', '--color=always', 'file'], root, dict(env, **variables))
+        check(what + ' status', result.returncode, 0)
+        output = result.stdout.decode()
+        if expected + inode not in output:
+            check(what + ' inode', output.split(inode)[0], expected)
+
+    # Mid grays, like the dashes of missing permissions, are bold black with eight colours
+    for variables, expected in [({'TERM': 'eight'}, '\x1b[1;30m-'),
+                                ({'TERM': 'sixteen'}, '\x1b[0;90m-'),
+                                ({'TERM': 'palette'}, '\x1b[0;38;5;244m-')]:
+        result = run(['-l', '--color=always', 'file'], root, dict(env, **variables))
+        if expected not in result.stdout.decode():
+            check(variables['TERM'] + ' missing permission', result.stdout.decode(), expected)
+
+finish()
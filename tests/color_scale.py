@@ -0,0 +1,110 @@
+import os
+import re
+import time
+
+from common import check, finish, run, scratch
+
+HOUR = 60 * 60
+DAY = 24 * HOUR
+
+# Sizes either side of each step of the scale, then the element that colours them
+sizes = [
+    ('size-a', 0, 'nb'),
+    ('size-b', 1023, 'nb'),
+    ('size-c', 1024, 'nk'),
+    ('size-d', (1 << 20) - 1, 'nk'),
+    ('size-e', 1 << 20, 'nm'),
+    ('size-f', (1 << 30) - 1, 'nm'),
+    ('size-g', 1 << 30, 'ng'),
+    ('size-h', 5 << 30, 'ng'),
+]
+
+# How long ago each file was changed, well inside its bucket, then the element that colours it
+ages = [
+    ('age-a', -DAY, 'dh'),
+    ('age-b', 10 * 60, 'dh'),
+    ('age-c', 5 * HOUR, 'dd'),
+    ('age-d', 3 * DAY, 'dw'),
+    ('age-e', 20 * DAY, 'dm'),
+    ('age-f', 100 * DAY, 'do'),
+    ('age-g', 1000 * DAY, 'do'),
+]
+
+PRESETS = {
+    'dark': {'nb': '0;32', 'nk': '1;32', 'nm': '1;33', 'ng': '1;31', 'dh': '1;36', 'dd': '0;36',
+             'dw': '1;34', 'dm': '0;34', 'do': '0;38;5;244', 'sn': '1;32', 'da': '0;34'},
+    'light': {'nb': '0;32', 'nk': '1;32', 'nm': '1;38;5;94', 'ng': '1;31', 'dh': '1;35',
+              'dd': '0;35', 'dw': '1;34', 'dm': '0;34', 'do': '0;38;5;244', 'sn': '1;32',
+              'da': '0;34'},
+}
+
+
+def style_of(line, pattern):
+    """The codes of the last escape before what `pattern` matches in `line`. Escapes are left out
+    where the style does not change, so that is the one it is shown in."""
+    match = re.search(pattern, line)
+    if match is None:
+        return None
+    escapes = re.findall('\x1b\\[([0-9;]*)m', line[:match.start()])
+    return escapes[-1] if escapes else None
+
+
+def size(n):
+    """Matches the size column of a long listing showing `n`"""
+    return r'(?<![0-9])%d \x1b' % n
+
+
+DATE = r'\w{3} [ \d]\d '
+
+with scratch() as (root, env):
+    env = dict(env, TERM='xterm-256color')
+    for name in ['COLORTERM', 'FLS_COLORS', 'NO_COLOR', 'LS_COLORS']:
+        env.pop(name, None)
+    now = time.time()
+    for name, n, _ in sizes:
+        with open(os.path.join(root, name), 'wb') as f:
+            f.truncate(n)
+        os.utime(os.path.join(root, name), (now - 1000 * DAY, now - 1000 * DAY))
+    for name, age, _ in ages:
+        open(os.path.join(root, name), 'w').close()
+        os.utime(os.path.join(root, name), (now - age, now - age))
+
+    def lines(args, variables={}):
+        result = run(['-l', '--color=always', *args], root, dict(env, **variables))
+        check(' '.join(args) + ' status', result.returncode, 0)
+        check(' '.join(args) + ' stderr', result.stderr, b'')
+        return {re.sub('\x1b\\[[0-9;]*m', '', line).split()[-1]: line
+                for line in result.stdout.decode().splitlines()[1:]}
+
+    for args, preset, scale_sizes, scale_ages in [
+            (['--color-scale'], 'dark', True, True),
+            (['--color-scale=all'], 'dark', True, True),
+            (['--color-scale=size'], 'dark', True, False),
+            (['--color-scale=age'], 'dark', False, True),
+            ([], 'dark', False, False),
+            (['--color-scale', '--theme=light'], 'light', True, True)]:
+        listing = lines(args)
+        codes = PRESETS[preset]
+        what = ' '.join(args)
+        for name, n, element in sizes:
+            expected = codes[element if scale_sizes else 'sn']
+            check(what + ' ' + name, style_of(listing.get(name, ''), size(n)), expected)
+        for name, _, element in ages:
+            expected = codes[element if scale_ages else 'da']
+            check(what + ' ' + name, style_of(listing.get(name, ''), DATE), expected)
+
+    # Each step can be restyled like any other theme element
+    elements = ['nb', 'nk', 'nm', 'ng', 'dh', 'dd', 'dw', 'dm', 'do']
+    codes = {element: '0;38;5;%d' % (100 + i) for i, element in enumerate(elements)}
+    colors = ':'.join('%s=%s' % (element, codes[element]) for element in elements)
+    listing = lines(['--color-scale'], {'FLS_COLORS': colors})
+    for name, n, element in sizes:
+        check('FLS_COLORS ' + name, style_of(listing.get(name, ''), size(n)), codes[element])
+    for name, _, element in ages:
+        check('FLS_COLORS ' + name, style_of(listing.get(name, ''), DATE), codes[element])
+
+    # Without colour there is nothing to scale
+    result = run(['-l', '--color=never', '--color-scale'], root, env)
+    check('uncoloured escapes', b'\x1b' in result.stdout, False)
+
+finish()
@@ -0,0 +1,85 @@
+"""What the tests share: building fls, a scratch directory that the user's own settings cannot
+reach, and keeping track of failures."""
+
+import contextlib
+import difflib
+import json
+import os
+import subprocess
+import sys
+import tempfile
+
+
+def build():
+    p = subprocess.run(['cargo', 'build', '--message-format=json'],
+                       stdout=subprocess.PIPE,
+                       check=True)
+    messages = [json.loads(m) for m in p.stdout.splitlines() if len(m) > 0]
+    return [m for m in messages if m.get('executable')][-1]['executable']
+
+
+exe_location = build()
+
+
+@contextlib.contextmanager
+def scratch():
+    """A temporary directory, and an environment in which no config file, FLS_OPTIONS, git
+    configuration or locale of the user's can change what fls or git do"""
+    with tempfile.TemporaryDirectory() as root:
+        env = dict(os.environ, HOME=root, XDG_CONFIG_HOME=root, LC_ALL='C')
+        env.pop('FLS_OPTIONS', None)
+        yield root, env
+
+
+def run(args, cwd=None, env=None, **kwargs):
+    return subprocess.run([exe_location, *args],
+                          cwd=cwd,
+                          env=env,
+                          stdout=subprocess.PIPE,
+                          stderr=subprocess.PIPE,
+                          **kwargs)
+
+
+failed = False
+
+
+def fail(*message):
+    global failed
+    failed = True
+    print(*message)
+
+
+def check(what, got, expected):
+    if got != expected:
+        fail(what, 'differs')
+        print('  expected:', repr(expected))
+        print('  got:     ', repr(got))
+
+
+def compare_with_ls(args, cwd=None, env=None, **kwargs):
+    """Runs fls and GNU ls with the same arguments and reports any difference in what they print
+    or how they exit"""
+    fls = run(args, cwd, env, **kwargs)
+    # Named fls, ls starts its messages the same way
+    ls = subprocess.run(['fls', *args],
+                        executable='/bin/ls',
+                        cwd=cwd,
+                        env=env,
+                        stdout=subprocess.PIPE,
+                        stderr=subprocess.PIPE,
+                        **kwargs)
+    what = ' '.join(args)
+    for stream, ours, theirs in [('stdout', fls.stdout, ls.stdout),
+                                 ('stderr', fls.stderr, ls.stderr)]:
+        if ours != theirs:
+            fail(what, stream, 'differs:')
+            ours = ours.decode(errors='replace').splitlines()
+            theirs = theirs.decode(errors='replace').splitlines()
+            for line in difflib.unified_diff(theirs, ours, 'ls', 'fls', n=1, lineterm=''):
+                print(' ', line)
+    check(what + ' exit status', fls.returncode, ls.returncode)
+    return fls
+
+
+def finish():
+    sys.exit(1 if failed else 0)
@@ -0,0 +1,61 @@
+import os
+import resource
+
+from common import check, compare_with_ls, fail, finish, run, scratch
+
+
+def few_files():
+    resource.setrlimit(resource.RLIMIT_NOFILE, (64, 64))
+
+
+def make_tree(root, name, depth):
+    """A chain of `depth` directories called `name`, each with a file in it. Created relative to
+    the one above, so that it can go deeper than PATH_MAX."""
+    fd = os.open(root, os.O_RDONLY)
+    for _ in range(depth):
+        os.mkdir(name, dir_fd=fd)
+        below = os.open(name, os.O_RDONLY, dir_fd=fd)
+        os.close(fd)
+        fd = below
+        os.close(os.open('file', os.O_CREAT | os.O_WRONLY, dir_fd=fd))
+    os.close(fd)
+
+
+def remove_tree(root, name, depth):
+    """Removes a tree made by make_tree, from the bottom up, which the temporary directory's own
+    cleanup cannot do with this many levels"""
+    fds = [os.open(root, os.O_RDONLY)]
+    for _ in range(depth):
+        fds.append(os.open(name, os.O_RDONLY, dir_fd=fds[-1]))
+    for fd in reversed(fds):
+        if fd != fds[-1]:
+            os.rmdir(name, dir_fd=fd)
+        if fd != fds[0]:
+            os.unlink('file', dir_fd=fd)
+        os.close(fd)
+
+
+with scratch() as (root, env):
+    # Far more levels than open files, but within PATH_MAX so that GNU ls can list it too
+    make_tree(root, 'd', 1500)
+    for args in [['-R', 'd'], ['-lR', 'd'], ['-RU', 'd']]:
+        sequential = compare_with_ls(args, root, env, preexec_fn=few_files)
+        parallel = run(['--parallel', *args], root, env, preexec_fn=few_files)
+        check('--parallel ' + ' '.join(args) + ' stdout', parallel.stdout, sequential.stdout)
+        check('--parallel ' + ' '.join(args) + ' status', parallel.returncode, 0)
+
+    # Deeper than PATH_MAX, so every directory at the bottom has to be opened from one above it
+    make_tree(root, 'deeper', 800)
+    for args in [['-R', 'deeper'], ['--parallel', '-lR', 'deeper']]:
+        result = run(args, root, env, preexec_fn=few_files)
+        check(' '.join(args) + ' status', result.returncode, 0)
+        check(' '.join(args) + ' stderr', result.stderr, b'')
+        headers = [l for l in result.stdout.splitlines() if l.endswith(b':')]
+        check(' '.join(args) + ' headers', len(headers), 800)
+        if headers and len(headers[-1]) < 800 * len('deeper/'):
+            fail(' '.join(args), 'ends at', headers[-1][-40:])
+
+    remove_tree(root, 'd', 1500)
+    remove_tree(root, 'deeper', 800)
+
+finish()
@@ -0,0 +1,42 @@
+import os
+
+from common import compare_with_ls, finish, scratch
+
+
+def as_nobody():
+    """Root can open any directory, so the ones that should fail are opened as another user"""
+    os.setgroups([])
+    os.setgid(65534)
+    os.setuid(65534)
+
+
+# Names that need no quoting, single quotes, double quotes, or control characters spelled out
+names = ['plain', 'with space', "it's", 'say "hi"', "both ' and \"", 'dollar $HOME', 'new\nline',
+         'tab\there', 'bell\a', "quote ' and\nnewline", 'back\\slash', '*glob?', '']
+
+with scratch() as (root, env):
+    os.chmod(root, 0o755)
+    os.makedirs(os.path.join(root, 'tree'))
+    for name in names[:-1]:
+        path = os.path.join(root, 'tree', name)
+        os.mkdir(path)
+        os.chmod(path, 0)
+    os.utime(os.path.join(root, 'tree'), (1000000000, 1000000000))
+
+    for lang in ['C', 'C.utf8']:
+        lang_env = dict(env, LC_ALL=lang)
+        # Cannot access operands that do not exist, whatever their names
+        compare_with_ls(['-d', *['missing/' + name for name in names]], root, lang_env)
+        # Characters outside ASCII, a C1 control and a byte that is not UTF-8
+        compare_with_ls(['-l', 'missing/é', 'tree/missing', os.fsdecode(b'missing/\xc2\x85'),
+                         os.fsdecode(b'missing/\xc3\xa9\xff')], root, lang_env)
+        # Cannot open directories, named on the command line or found while recursing. The
+        # operands go in the order GNU sorts them, since fls lists directory operands as they come.
+        operands = sorted(os.path.join('tree', name).encode() for name in names[:-1])
+        compare_with_ls([o.decode() for o in operands], root, lang_env, preexec_fn=as_nobody)
+        compare_with_ls(['-R', 'tree'], root, lang_env, preexec_fn=as_nobody)
+
+    for name in names[:-1]:
+        os.chmod(os.path.join(root, 'tree', name), 0o755)
+
+finish()
@@ -0,0 +1,31 @@
+import os
+
+from common import compare_with_ls, finish, scratch
+
+with scratch() as (root, env):
+    os.makedirs(os.path.join(root, 'top', 'sub', 'deeper'))
+    os.makedirs(os.path.join(root, 'other'))
+    for name in ['top/plain', 'top/with space', 'top/sub/café', 'top/sub/deeper/last',
+                 'other/x']:
+        with open(os.path.join(root, name), 'w') as f:
+            f.write(name)
+    os.symlink('plain', os.path.join(root, 'top', 'link'))
+    # Names ls reports the same way whatever time it is
+    for directory, _, names in os.walk(root):
+        for name in names + ['.']:
+            os.utime(os.path.join(directory, name), (1000000000, 1000000000),
+                     follow_symlinks=False)
+
+    # The offsets count bytes of output, so every kind of line before a name has to agree
+    for args in [['-lD', 'top'], ['-lDR', 'top'], ['-lD', 'other', 'top'], ['-lDA', 'top'],
+                 ['-lDR', 'other', 'top'], ['-lDi', 'top'], ['-D', 'top'], ['-lD', 'missing']]:
+        compare_with_ls(args, root, env)
+    # A listing big enough to need several writes
+    many = os.path.join(root, 'many')
+    os.mkdir(many)
+    for i in range(3000):
+        open(os.path.join(many, 'file%05d' % i), 'w').close()
+        os.utime(os.path.join(many, 'file%05d' % i), (1000000000, 1000000000))
+    compare_with_ls(['-lD', 'many'], root, env)
+
+finish()
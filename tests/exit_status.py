@@ -0,0 +1,47 @@
+import os
+
+from common import check, compare_with_ls, finish, scratch
+
+
+def as_nobody():
+    """Root can read any directory, so the listings that should fail run as an unprivileged user"""
+    os.setgroups([])
+    os.setgid(65534)
+    os.setuid(65534)
+
+
+# Arguments, then the exit status GNU ls documents for them: 0 if all went well, 1 for minor
+# problems such as a subdirectory that cannot be read, and 2 for serious trouble such as an
+# operand that cannot be accessed
+cases = [
+    (['file'], 0),
+    (['-R', 'tree/open'], 0),
+    (['missing'], 2),
+    (['file', 'missing'], 2),
+    (['-a', 'missing', 'file'], 2),
+    (['-d', 'missing', 'file'], 2),
+    (['-d', 'tree/locked'], 0),
+    (['tree/locked'], 2),
+    (['-l', 'tree/locked'], 2),
+    (['-R', 'tree'], 1),
+    (['-lR', 'tree'], 1),
+    (['-a', 'tree/locked/inside'], 2),
+    (['-R', 'tree', 'missing'], 2),
+]
+
+with scratch() as (root, env):
+    os.chmod(root, 0o755)
+    open(os.path.join(root, 'file'), 'w').close()
+    for path in ['tree/open/below', 'tree/locked/inside']:
+        os.makedirs(os.path.join(root, path))
+    os.chmod(os.path.join(root, 'tree', 'locked'), 0)
+    for path, _, _ in os.walk(root):
+        os.utime(path, (1000000000, 1000000000))
+
+    for args, status in cases:
+        result = compare_with_ls(args, root, env, preexec_fn=as_nobody)
+        check(' '.join(args) + ' status', result.returncode, status)
+
+    os.chmod(os.path.join(root, 'tree', 'locked'), 0o755)
+
+finish()
@@ -0,0 +1,36 @@
+import os
+
+from common import check, compare_with_ls, fail, finish, run, scratch
+
+with scratch() as (root, env):
+    for age, (name, size) in enumerate([('empty', 0), ('small', 100), ('large', 100000)]):
+        with open(os.path.join(root, name), 'w') as f:
+            f.write('x' * size)
+        os.utime(os.path.join(root, name), (1000000000 - age, 1000000000 - age))
+    os.mkdir(os.path.join(root, 'dir'))
+    open(os.path.join(root, 'dir', 'inside'), 'w').close()
+    os.symlink('large', os.path.join(root, 'link'))
+    os.chmod(os.path.join(root, 'small'), 0o755)
+
+    # Inodes of files named on the command line come from their own stat
+    for args in [['-i'], ['-i1'], ['-iF'], ['-it'], ['-iL'], ['-ix'], ['-iS']]:
+        for operands in [['empty', 'small', 'large'], ['link', 'small'], ['large', 'dir']]:
+            compare_with_ls([*args, *operands], root, env)
+
+    # So do their block counts, which are the same as when their directory is listed
+    listed = run(['-s1', '.'], root, env).stdout.decode().splitlines()[1:]
+    blocks = {line.split()[1]: line.split()[0] for line in listed}
+    for args in [['-s1'], ['-si1'], ['-s1F'], ['-s1L']]:
+        result = run([*args, 'empty', 'small', 'large', 'link'], root, env)
+        check(' '.join(args) + ' status', result.returncode, 0)
+        for line in result.stdout.decode().splitlines():
+            fields = line.split()
+            if fields[0] == 'total':
+                continue
+            shown = fields[1] if 'i' in args[0] else fields[0]
+            name = fields[-1].rstrip('*@')
+            expected = blocks['large' if name == 'link' and 'L' in args[0] else name]
+            if shown != expected:
+                fail(' '.join(args), name, 'has', shown, 'blocks instead of', expected)
+
+finish()
@@ -0,0 +1,47 @@
+import os
+import subprocess
+import sys
+
+from common import check, fail, finish, run, scratch
+
+with scratch() as (root, env):
+    top = os.path.join(root, 'top')
+    mount = os.path.join(top, 'mnt')
+    for path in ['top/sub/deeper', 'top/mnt', 'top/z']:
+        os.makedirs(os.path.join(root, path))
+    open(os.path.join(root, 'top', 'sub', 'file'), 'w').close()
+    # Mounting needs root, and the test means nothing without a second filesystem
+    if subprocess.run(['mount', '-t', 'tmpfs', 'fls-test', mount],
+                      stderr=subprocess.DEVNULL).returncode != 0:
+        print('skipped: cannot mount a tmpfs')
+        sys.exit(0)
+    try:
+        os.makedirs(os.path.join(mount, 'inside', 'further'))
+        open(os.path.join(mount, 'inside', 'file'), 'w').close()
+
+        for args in [['-R'], ['-lR'], ['-RU'], ['-lRU'], ['--parallel', '-R'],
+                     ['--parallel', '-lR']]:
+            everything = run([*args, 'top'], root, env)
+            check(' '.join(args) + ' status', everything.returncode, 0)
+            if b'top/mnt/inside:' not in everything.stdout.splitlines():
+                fail(' '.join(args), 'does not go into the mount')
+            # The mount point is still shown, but nothing in it is listed
+            sections = everything.stdout.split(b'\n\n')
+            expected = b'\n\n'.join(s for s in sections if not s.startswith(b'top/mnt'))
+            if not expected.endswith(b'\n'):
+                expected += b'\n'
+            for option in ['--one-file-system', '--skip-fs=tmpfs', '--skip-fs=proc,tmpfs']:
+                result = run([option, *args, 'top'], root, env)
+                what = ' '.join([option, *args])
+                check(what + ' stdout', result.stdout, expected)
+                check(what + ' status', result.returncode, 0)
+            # Operands are always listed, whatever they are on
+            result = run(['--skip-fs=tmpfs', *args, 'top/mnt'], root, env)
+            check('--skip-fs=tmpfs on the operand', result.stdout.split(b'\n')[0], b'top/mnt:')
+            # Other filesystems are left alone
+            result = run(['--skip-fs=proc', *args, 'top'], root, env)
+            check('--skip-fs=proc', result.stdout, everything.stdout)
+    finally:
+        subprocess.run(['umount', mount], check=True)
+
+finish()
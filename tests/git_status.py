@@ -0,0 +1,147 @@
+import os
+import subprocess
+import time
+
+from common import fail, finish, run, scratch
+
+# The letters fls shows, in the order a directory picks the most important one of its contents
+ORDER = '-INMU'
+STAGED = {' ': '-', 'M': 'M', 'T': 'M', 'A': 'N', 'D': 'M'}
+UNSTAGED = {' ': '-', 'M': 'M', 'T': 'M', 'D': 'M'}
+
+
+def expected_status(path, is_dir, porcelain):
+    # Untracked and ignored directories are reported as a whole. A file removed from the index
+    # but still in the work tree is reported twice, and fls shows it as untracked.
+    for status, letters in [('??', '-N'), ('!!', '-I')]:
+        for s, changed in porcelain:
+            if s == status and (changed in (path, path + '/') or
+                                changed.endswith('/') and path.startswith(changed)):
+                return letters
+    for status, changed in porcelain:
+        if changed in (path, path + '/') and not is_dir:
+            return STAGED[status[0]] + UNSTAGED[status[1]]
+    if not is_dir:
+        return '--'
+    staged, unstaged = '-', '-'
+    for status, changed in porcelain:
+        if not changed.startswith(path + '/') or status == '!!':
+            continue
+        if status == '??':
+            unstaged = max(unstaged, 'N', key=ORDER.index)
+        else:
+            staged = max(staged, STAGED[status[0]], key=ORDER.index)
+            unstaged = max(unstaged, UNSTAGED[status[1]], key=ORDER.index)
+    return staged + unstaged
+
+
+def write(root, name, contents):
+    path = os.path.join(root, name)
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(path, 'w') as f:
+        f.write(contents)
+    # Files written in the same second as the index make git compare contents, which fls does not
+    day_ago = time.time() - 24 * 60 * 60
+    os.utime(path, (day_ago, day_ago))
+
+
+with scratch() as (root, env):
+    env = dict(env,
+               GIT_AUTHOR_NAME='fls',
+               GIT_AUTHOR_EMAIL='fls@example.com',
+               GIT_COMMITTER_NAME='fls',
+               GIT_COMMITTER_EMAIL='fls@example.com')
+
+    def git(*args):
+        subprocess.run(['git', *args], cwd=root, env=env, check=True, stdout=subprocess.DEVNULL)
+
+    git('init', '-q')
+    lines = ''.join('line %d\n' % i for i in range(2000))
+    for name in ['big', 'clean/a', 'clean/deep/b', 'modified/a', 'modified/b', 'staged/a',
+                 'removed/a', 'removed/b', 'deleted/a', 'deleted/b', 'untracked/a',
+                 'nested/one/two/a', 'ignored/a', 'mode/run']:
+        write(root, name, lines if name == 'big' else name + '\n')
+    write(root, '.gitignore', '*.o\nbuild/\n')
+    git('add', '.')
+    git('commit', '-q', '-m', 'first')
+
+    # A second commit of a slightly different big file, packed, makes HEAD a chain of deltas
+    write(root, 'big', lines + 'one more line\n')
+    git('commit', '-q', '-a', '-m', 'second')
+    git('gc', '-q', '--aggressive')
+
+    write(root, 'modified/a', 'changed in the work tree\n')
+    write(root, 'staged/a', 'changed in the index\n')
+    write(root, 'staged/new', 'new in the index\n')
+    git('add', 'staged')
+    write(root, 'staged/a', 'changed in the index, then in the work tree\n')
+    git('rm', '-q', '--cached', 'removed/a')
+    os.remove(os.path.join(root, 'deleted/a'))
+    write(root, 'untracked/new', 'untracked\n')
+    write(root, 'nested/one/two/new', 'untracked deep down\n')
+    write(root, 'brand/new/file', 'in an untracked directory\n')
+    write(root, 'ignored/a.o', 'ignored\n')
+    write(root, 'build/out', 'in an ignored directory\n')
+    write(root, 'top.o', 'ignored\n')
+    os.chmod(os.path.join(root, 'mode/run'), 0o755)
+
+    porcelain = subprocess.run(['git', 'status', '--porcelain', '-z', '--ignored'],
+                               cwd=root,
+                               env=env,
+                               stdout=subprocess.PIPE,
+                               check=True).stdout.decode()
+    porcelain = [(l[:2], l[3:]) for l in porcelain.split('\0') if l]
+
+    for directory, dirs, names in os.walk(root):
+        if '.git' in dirs:
+            dirs.remove('.git')
+        relative = os.path.relpath(directory, root)
+        fls_output = run(['-l', '-A', '--git', relative], root, env).stdout.decode()
+        for line in fls_output.splitlines():
+            fields = line.split(maxsplit=9)
+            if len(fields) < 10 or fields[9] == '.git':
+                continue
+            shown, name = fields[8], fields[9]
+            path = os.path.normpath(os.path.join(relative, name))
+            expected = expected_status(path, line.startswith('d'), porcelain)
+            if shown != expected:
+                fail(path, 'shows', shown, 'but git status says', expected)
+
+    # Files and directories named on the command line, from the root and from deeper down
+    operands = ['top.o', 'ignored/a.o', 'modified/a', 'untracked/new', 'build', 'nested/one',
+                'clean']
+    for cwd in ['.', 'modified']:
+        args = [os.path.relpath(operand, cwd) for operand in operands]
+        fls_output = run(['-ld', '--git', *args], os.path.join(root, cwd), env).stdout.decode()
+        for line in fls_output.splitlines():
+            fields = line.split(maxsplit=9)
+            if len(fields) < 10:
+                continue
+            shown, name = fields[8], fields[9]
+            path = os.path.normpath(os.path.join(cwd, name))
+            expected = expected_status(path, line.startswith('d'), porcelain)
+            if shown != expected:
+                fail(name, 'from', cwd, 'shows', shown, 'but git status says', expected)
+
+    # Every listed directory that is the root of a work tree says what is checked out, right
+    # after its header when there is one
+    subprocess.run(['git', 'init', '-q', 'sub'], cwd=root, env=env, check=True)
+    subprocess.run(['git', '-C', 'sub', 'checkout', '-q', '-b', 'side'], cwd=root, env=env,
+                   check=True)
+    branch = subprocess.run(['git', 'symbolic-ref', '--short', 'HEAD'], cwd=root, env=env,
+                            stdout=subprocess.PIPE, check=True).stdout.decode().strip()
+    for args, heads in [(['.'], {None: branch}),
+                        (['.', 'sub', 'clean'], {'.': branch, 'sub': 'side'}),
+                        (['-R', '.'], {'.': branch, './sub': 'side'}),
+                        (['-R', 'sub'], {'sub': 'side'})]:
+        lines = run(['-l', '--git', *args], root, env).stdout.decode().splitlines()
+        found = {}
+        header = None
+        for line in lines:
+            if line.startswith('On branch '):
+                found[header] = line[len('On branch '):]
+            header = line[:-1] if line.endswith(':') else None
+        if found != heads:
+            fail(' '.join(args), 'shows branches', found, 'instead of', heads)
+
+finish()
@@ -0,0 +1,96 @@
+import os
+import subprocess
+
+from common import fail, finish, run, scratch
+
+# Every kind of rule git has: names at any depth, anchored paths, directory-only rules, **,
+# negation, character classes, escapes, and rules from nested .gitignore files
+files = [
+    'a/x.log',
+    'a/b/y.log',
+    'a/b/c/z.txt',
+    'a/b/keep.log',
+    'build/out',
+    'docs/build/x',
+    'docs/readme.md',
+    'docs/notes.txt',
+    'logs/1.log',
+    'logs/keep.log',
+    'keep/k.tmp',
+    'sub/deep/f.o',
+    'sub/g.o',
+    'sub/deep/g.o',
+    'src/a/gen/x.rs',
+    'src/gen/y.rs',
+    'gen/z.rs',
+    'root.tmp',
+    'top.txt',
+    'nested/top.txt',
+    'file1',
+    'file2',
+    'filex',
+    '#hash',
+    '!bang',
+    'trailing ',
+    'only-dir',
+    'only-dir-d/x',
+]
+gitignore = '''\
+*.log
+!keep.log
+build/
+/top.txt
+**/c/
+*.tmp
+!keep/*.tmp
+src/**/gen/
+file[0-9]
+\\#hash
+\\!bang
+only-dir/
+only-dir-d/
+docs/*.txt
+'''
+sub_gitignore = '''\
+*.o
+!g.o
+deep/g.o
+'''
+
+with scratch() as (root, env):
+    subprocess.run(['git', 'init', '-q', root], check=True)
+    for name in files:
+        path = os.path.join(root, name)
+        os.makedirs(os.path.dirname(path), exist_ok=True)
+        open(path, 'w').close()
+    with open(os.path.join(root, '.gitignore'), 'w') as f:
+        f.write(gitignore)
+    with open(os.path.join(root, 'sub', '.gitignore'), 'w') as f:
+        f.write(sub_gitignore)
+
+    for directory, dirs, names in os.walk(root):
+        relative = os.path.relpath(directory, root)
+        entries = sorted(dirs + names)
+        if '.git' in dirs:
+            dirs.remove('.git')
+        paths = [os.path.normpath(os.path.join(relative, e)) for e in entries]
+
+        check = subprocess.run(['git', 'check-ignore', '--stdin', '-z'],
+                               cwd=root,
+                               env=env,
+                               input='\0'.join(paths).encode(),
+                               stdout=subprocess.PIPE)
+        ignored = set(check.stdout.decode().split('\0'))
+        expected = [e for e, p in zip(entries, paths) if p not in ignored]
+
+        fls_output = run(['-A', '-1', '--gitignore', relative], root, env).stdout.decode()
+        listed = sorted(l for l in fls_output.split('\n') if l)
+
+        if listed != sorted(expected):
+            fail(relative, 'differs:')
+            for e in sorted(set(listed) - set(expected)):
+                print('  shown but ignored by git:', repr(e))
+            for e in sorted(set(expected) - set(listed)):
+                print('  hidden but not ignored by git:', repr(e))
+
+finish()
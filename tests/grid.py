@@ -0,0 +1,30 @@
+import os
+import random
+
+from common import compare_with_ls, finish, scratch
+
+# Names of very different lengths, where the widest layout is rarely the first one that fails
+random.seed(29)
+names = set()
+while len(names) < 40:
+    length = random.choice([1, 2, 3, 5, 8, 13, 21])
+    names.add(''.join(random.choice('abcdefghij') for _ in range(length)))
+names = sorted(names)
+random.shuffle(names)
+
+with scratch() as (root, env):
+    # GNU ls pads with tabs where it can unless told otherwise
+    env = dict(env, TABSIZE='0')
+    for letter, count in [('a', 1), ('b', 2), ('c', 7), ('d', 40)]:
+        listing = os.path.join(root, letter * count)
+        os.mkdir(listing)
+        for name in names[:count]:
+            open(os.path.join(listing, name), 'w').close()
+        for width in range(1, 140):
+            for direction in ['-x', '-C']:
+                compare_with_ls([direction, '-w', str(width), listing], env=env)
+        compare_with_ls(['-x', '-w', '100', '-i', '-s', listing], env=env)
+    for width in [10, 20, 30]:
+        compare_with_ls(['-C', '-F', '-w', str(width), root], env=env)
+
+finish()
@@ -0,0 +1,49 @@
+import os
+import socket
+
+from common import compare_with_ls, finish, scratch
+
+with scratch() as (root, env):
+    listing = os.path.join(root, 'listing')
+    os.mkdir(listing)
+    os.mkdir(os.path.join(listing, 'dir'))
+    open(os.path.join(listing, 'dir', 'inside'), 'w').close()
+    open(os.path.join(listing, 'file'), 'w').close()
+    open(os.path.join(listing, 'run'), 'w').close()
+    os.chmod(os.path.join(listing, 'run'), 0o755)
+    os.mkfifo(os.path.join(listing, 'fifo'))
+    server = socket.socket(socket.AF_UNIX)
+    server.bind(os.path.join(listing, 'sock'))
+    os.symlink('dir', os.path.join(listing, 'ldir'))
+    os.symlink('run', os.path.join(listing, 'lrun'))
+    os.symlink('nowhere', os.path.join(listing, 'broken'))
+    # GNU ls reports broken links under -L, so the links that get followed have a directory of
+    # their own
+    os.mkdir(os.path.join(listing, 'links'))
+    os.symlink('../dir', os.path.join(listing, 'links', 'ldir'))
+    os.symlink('../run', os.path.join(listing, 'links', 'lrun'))
+    os.symlink('../fifo', os.path.join(listing, 'links', 'lfifo'))
+    for name in os.listdir(listing) + ['.']:
+        os.utime(os.path.join(listing, name), (1000000000, 1000000000), follow_symlinks=False)
+
+    # Later options replace earlier ones, and stdout is not a terminal here, so auto is a no-op
+    styles = [['-F'], ['--classify'], ['--classify=always'], ['--classify=never'],
+              ['--classify=auto'], ['--file-type'], ['-p'], ['--indicator-style=none'],
+              ['--indicator-style=slash'], ['--indicator-style=file-type'],
+              ['--indicator-style=classify'], ['--file-type', '--classify=auto'],
+              ['--classify=auto', '--file-type'], ['-p', '--classify=auto'],
+              ['-F', '--classify=auto'], ['-F', '--file-type'], ['-p', '-F'],
+              ['-F', '--indicator-style=none']]
+    for style in styles:
+        compare_with_ls([*style, '.'], listing, env)
+        compare_with_ls([*style, '-L', 'links', 'lrun', 'run'], listing, env)
+        # Symlinks on the command line are followed to directories unless the listing would
+        # say what they are
+        compare_with_ls([*style, 'ldir', 'lrun', 'broken', 'file'], listing, env)
+        compare_with_ls([*style, '-H', 'ldir', 'lrun'], listing, env)
+        compare_with_ls([*style, '-d', 'ldir'], listing, env)
+    for args in [['ldir'], ['-L', 'ldir'], ['-R', 'ldir'], ['-H', 'broken'], ['-L', 'broken']]:
+        compare_with_ls(args, listing, env)
+    server.close()
+
+finish()
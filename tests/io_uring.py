@@ -0,0 +1,31 @@
+import os
+
+from common import check, finish, run, scratch
+
+with scratch() as (root, env):
+    top = os.path.join(root, 'top')
+    os.makedirs(os.path.join(top, 'sub'))
+    # More entries than one batch of the ring holds
+    for i in range(1000):
+        path = os.path.join(top, 'f%04d' % i)
+        with open(path, 'w') as f:
+            f.write('x' * (i % 37))
+        os.utime(path, (1000000000 + i, 1000000000 + i * 7919 % 1000))
+    os.symlink('f0001', os.path.join(top, 'link'))
+    os.symlink('nowhere', os.path.join(top, 'broken'))
+    os.symlink('sub', os.path.join(top, 'ldir'))
+    os.mkfifo(os.path.join(top, 'fifo'))
+
+    # The ring is only an optimisation, so nothing it does may show
+    for args in [['-l'], ['-lR'], ['-lS'], ['-lt'], ['-lU'], ['-s'], ['-lL'], ['-lRL'], ['-t'],
+                 ['-lF', '--color=always'], ['-li'], ['-l', 'top/f0001', 'top/link']]:
+        if 'top/link' not in args:
+            args = [*args, 'top']
+        plain = run(args, root, env)
+        ring = run(['--io-uring', *args], root, env)
+        what = ' '.join(['--io-uring', *args])
+        check(what + ' stdout', ring.stdout, plain.stdout)
+        check(what + ' stderr', ring.stderr, plain.stderr)
+        check(what + ' exit status', ring.returncode, plain.returncode)
+
+finish()
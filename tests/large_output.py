@@ -0,0 +1,33 @@
+import os
+import subprocess
+
+from common import check, compare_with_ls, exe_location, finish, scratch
+
+with scratch() as (root, env):
+    # Several megabytes of output, with names long enough to straddle buffer boundaries
+    for i in range(40):
+        directory = os.path.join(root, 'tree', 'directory-%02d' % i)
+        os.makedirs(directory)
+        for j in range(300):
+            path = os.path.join(directory, 'file-%03d-' % j + 'é' * (j % 90))
+            open(path, 'w').close()
+            os.utime(path, (1000000000, 1000000000))
+        os.utime(directory, (1000000000, 1000000000))
+    os.utime(os.path.join(root, 'tree'), (1000000000, 1000000000))
+
+    for args in [['-R'], ['-lR'], ['-R1'], ['-lRi'], ['-RU', '-l']]:
+        compare_with_ls([*args, 'tree'], root, env)
+
+    # Written to a file rather than a pipe, which may be buffered differently
+    for args in [['-lR'], ['-R']]:
+        outputs = []
+        for program in [exe_location, '/bin/ls']:
+            path = os.path.join(root, 'output')
+            with open(path, 'wb') as f:
+                subprocess.run([program, *args, 'tree'], cwd=root, env=env, stdout=f, check=True)
+            with open(path, 'rb') as f:
+                outputs.append(f.read())
+            os.remove(path)
+        check(' '.join(args) + ' into a file', outputs[0], outputs[1])
+
+finish()
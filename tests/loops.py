@@ -0,0 +1,26 @@
+import os
+
+from common import check, compare_with_ls, finish, run, scratch
+
+with scratch() as (root, env):
+    os.makedirs(os.path.join(root, 'a', 'b', 'c'))
+    open(os.path.join(root, 'a', 'file'), 'w').close()
+    # Back to an ancestor, back to the directory itself, across to a sibling, and to a file
+    os.symlink('..', os.path.join(root, 'a', 'b', 'c', 'up'))
+    os.symlink('.', os.path.join(root, 'a', 'self'))
+    os.symlink('b/c', os.path.join(root, 'a', 'across'))
+    os.symlink('file', os.path.join(root, 'a', 'lfile'))
+    # Only a link leads into this one
+    os.makedirs(os.path.join(root, 'elsewhere', 'inside'))
+    os.symlink('../elsewhere', os.path.join(root, 'a', 'away'))
+
+    for args in [['-RL'], ['-lRL'], ['-RLF'], ['-RL1'], ['-RLa'], ['-R'], ['-lR']]:
+        for operands in [['a'], ['a/b'], ['a', 'elsewhere']]:
+            sequential = compare_with_ls([*args, *operands], root, env)
+            parallel = run(['--parallel', *args, *operands], root, env)
+            what = ' '.join(['--parallel', *args, *operands])
+            check(what + ' stdout', parallel.stdout, sequential.stdout)
+            check(what + ' stderr', parallel.stderr, sequential.stderr)
+            check(what + ' exit status', parallel.returncode, sequential.returncode)
+
+finish()
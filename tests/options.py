@@ -0,0 +1,116 @@
+import os
+
+from common import check, fail, finish, run, scratch
+
+TRY_HELP = "Try 'fls --help' for more information.\n"
+
+# Arguments, then the exit status and what fls should print to stderr
+errors = [
+    (['--colr'], 2, "fls: unrecognized option '--colr'\nDid you mean '--color'?\n" + TRY_HELP),
+    (['--reverse', '--almost-al'], 2,
+     "fls: unrecognized option '--almost-al'\nDid you mean '--almost-all'?\n" + TRY_HELP),
+    (['--xyzzy-plugh'], 2, "fls: unrecognized option '--xyzzy-plugh'\n" + TRY_HELP),
+    (['-j'], 2, "fls: invalid option -- 'j'\n" + TRY_HELP),
+    (['-lj'], 2, "fls: invalid option -- 'j'\n" + TRY_HELP),
+    (['--sort'], 2, "fls: option '--sort' requires an argument\n" + TRY_HELP),
+    (['--all=x'], 2, "fls: option '--all' doesn't allow an argument\n" + TRY_HELP),
+    (['--sort=big'], 2,
+     "fls: invalid argument 'big' for '--sort'\nValid arguments are:\n"
+     "  - 'none'\n  - 'name'\n  - 'size'\n  - 'time'\n" + TRY_HELP),
+    (['--time=birth'], 2,
+     "fls: invalid argument 'birth' for '--time'\nValid arguments are:\n"
+     "  - 'atime'\n  - 'access'\n  - 'use'\n  - 'ctime'\n  - 'status'\n"
+     "  - 'mtime'\n  - 'modification'\n" + TRY_HELP),
+]
+
+# The config file, FLS_OPTIONS, arguments, then the exit status, stdout and stderr. CONFIG stands
+# for the path of the config file.
+defaults = [
+    ('# hidden files too\n-A\n', None, [], 0, '.hidden\nfile\n', ''),
+    ('', '-A', [], 0, '.hidden\nfile\n', ''),
+    ('-A\n', '-A', ['--no-config'], 0, 'file\n', ''),
+    ('--color=always\n', '--color=always', ['--color=never'], 0, 'file\n', ''),
+    ('-A\n--colr\n', None, [], 2, '',
+     "fls: CONFIG:2: unrecognized option '--colr'\nDid you mean '--color'?\n" + TRY_HELP),
+    ('-A file\n', None, [], 2, '', "fls: CONFIG:1: not an option: 'file'\n" + TRY_HELP),
+    ('', '--all=x', [], 2, '',
+     "fls: FLS_OPTIONS: option '--all' doesn't allow an argument\n" + TRY_HELP),
+    ('', '--sort', [], 2, '', "fls: FLS_OPTIONS: option '--sort' requires an argument\n" + TRY_HELP),
+    # Any of these would make every run print it and exit
+    ('-A\n--version\n', '--help', [], 2, '',
+     "fls: CONFIG:2: option '--version' is only allowed on the command line\n"
+     "fls: FLS_OPTIONS: option '--help' is only allowed on the command line\n" + TRY_HELP),
+    ('', '--completions=bash', [], 2, '',
+     "fls: FLS_OPTIONS: option '--completions' is only allowed on the command line\n" + TRY_HELP),
+]
+
+with scratch() as (root, env):
+    path = os.path.join(root, 'file')
+    open(path, 'w').close()
+    os.utime(path, (1000000000, 1200000000))
+
+    for args, status, stderr in errors:
+        result = run(args, root, env)
+        check(' '.join(args) + ' status', result.returncode, status)
+        check(' '.join(args) + ' stderr', result.stderr.decode(), stderr)
+        check(' '.join(args) + ' stdout', result.stdout.decode(), '')
+
+    config = os.path.join(root, 'fls', 'config')
+    os.mkdir(os.path.dirname(config))
+    listing = os.path.join(root, 'listing')
+    os.mkdir(listing)
+    for name in ['file', '.hidden']:
+        open(os.path.join(listing, name), 'w').close()
+    for contents, fls_options, args, status, stdout, stderr in defaults:
+        what = ' '.join([repr(contents), repr(fls_options), *args])
+        with open(config, 'w') as f:
+            f.write(contents)
+        defaults_env = dict(env) if fls_options is None else dict(env, FLS_OPTIONS=fls_options)
+        result = run(['-1', *args], listing, defaults_env)
+        check(what + ' status', result.returncode, status)
+        check(what + ' stdout', result.stdout.decode(), stdout)
+        check(what + ' stderr', result.stderr.decode(), stderr.replace('CONFIG', config))
+    os.remove(config)
+
+    # Every option in --help that needs no value is one fls accepts, and the groups have headings
+    result = run(['--help'], root, env)
+    check('--help status', result.returncode, 0)
+    help_text = result.stdout.decode()
+    for heading in ['Choosing entries:', 'Sorting:']:
+        if heading not in help_text.splitlines():
+            fail('--help has no', repr(heading), 'heading')
+    for line in help_text.splitlines():
+        # Options take up the first 32 columns, and their descriptions the rest
+        options = line[:32].strip()
+        if not options.startswith('-') or '=' in options and '[=' not in options:
+            continue
+        for option in options.replace(',', ' ').split():
+            option = option.split('[')[0]
+            if option in ('--help', '--version'):
+                continue
+            accepted = run([option, root], root, env)
+            if accepted.returncode == 2:
+                fail('--help lists', option, 'but it is rejected:', accepted.stderr.decode())
+
+    result = run(['--version'], root, env)
+    check('--version status', result.returncode, 0)
+    version = result.stdout.decode()
+    if not version.startswith('fls ') or version.count('\n') != 2:
+        fail('--version prints', repr(version))
+    # The tests build fls with the default features, which are all of them
+    check('--version features', version.splitlines()[-1], 'Features: +git +io-uring +parallel')
+
+    # Each time field shows the time it names. The modification and access times are set far
+    # apart in the past, while the status change time is when utime ran.
+    for args, year in [([], '2008'), (['--time=mtime'], '2008'), (['--time=atime'], '2001'),
+                       (['-u'], '2001'), (['--time=ctime'], None), (['-c'], None)]:
+        result = run(['-l', *args, 'file'], root, env)
+        # Times from more than half a year ago have a year where recent ones have the time of day
+        shown = result.stdout.decode().splitlines()[-1].split()[7]
+        if year is None:
+            if shown in ('2001', '2008'):
+                fail(' '.join(args), 'shows', shown, 'instead of the status change time')
+        else:
+            check(' '.join(args) + ' year', shown, year)
+
+finish()
@@ -0,0 +1,29 @@
+import os
+import pwd
+import sys
+
+from common import compare_with_ls, finish, scratch
+
+if os.geteuid() != 0:
+    print('skipped: changing owners needs root')
+    sys.exit(0)
+
+# Every user and group on the system, and ids no name is given to
+users = sorted({u.pw_uid for u in pwd.getpwall()} | {12345, 4000000000})
+groups = sorted({u.pw_gid for u in pwd.getpwall()} | {54321, 4000000001})
+
+with scratch() as (root, env):
+    listing = os.path.join(root, 'listing')
+    os.makedirs(os.path.join(listing, 'sub'))
+    for i in range(max(len(users), len(groups)) * 2):
+        path = os.path.join(listing, 'sub' if i % 5 == 0 else '', 'f%03d' % i)
+        open(path, 'w').close()
+        os.chown(path, users[i % len(users)], groups[i // 2 % len(groups)])
+        os.utime(path, (1000000000, 1000000000))
+    os.utime(os.path.join(listing, 'sub'), (1000000000, 1000000000))
+
+    # The names are looked up once and shared by every directory
+    for args in [['-l'], ['-lR'], ['-ln'], ['-lg'], ['-lo'], ['-lnR']]:
+        compare_with_ls([*args, 'listing'], root, env)
+
+finish()
@@ -0,0 +1,29 @@
+import os
+
+from common import check, finish, run, scratch
+
+with scratch() as (root, env):
+    # Wide and deep enough that the workers are still reading ahead when output catches up
+    for i in range(30):
+        for j in range(10):
+            path = os.path.join(root, 'tree', 'd%02d' % i, 'e%02d' % j)
+            os.makedirs(path)
+            for k in range(i % 7):
+                open(os.path.join(path, 'f%d' % k), 'w').close()
+    os.makedirs(os.path.join(root, 'tree', 'deep', *['level'] * 60))
+    os.symlink('../tree', os.path.join(root, 'tree', 'loop'))
+    os.mkfifo(os.path.join(root, 'tree', 'fifo'))
+
+    for args in [['-R'], ['-Ra'], ['-lR'], ['-RF'], ['-RU'], ['-RS'], ['-Rlt'], ['-RL'],
+                 ['-R', 'tree/d01', 'tree/d02']]:
+        if len(args) == 1:
+            args = [*args, 'tree']
+        sequential = run(args, root, env)
+        for threads in ['', '=1', '=4', '=64']:
+            parallel = run(['--parallel' + threads, *args], root, env)
+            what = ' '.join(['--parallel' + threads, *args])
+            check(what + ' stdout', parallel.stdout, sequential.stdout)
+            check(what + ' stderr', parallel.stderr, sequential.stderr)
+            check(what + ' exit status', parallel.returncode, sequential.returncode)
+
+finish()
@@ -0,0 +1,41 @@
+import os
+import time
+
+from common import fail, finish, run, scratch
+
+MINUTE = 60
+HOUR = 60 * MINUTE
+DAY = 24 * HOUR
+
+# How long ago each file was modified, then how --time-style=relative and compact show it. The
+# ages are well inside their unit so that the seconds it takes to run fls do not change them.
+files = [
+    ('one-minute', MINUTE + 20, '1 minute ago', '1m'),
+    ('minutes', 5 * MINUTE + 20, '5 minutes ago', '5m'),
+    ('one-hour', HOUR + 5 * MINUTE, '1 hour ago', '1h'),
+    ('hours', 3 * HOUR + 5 * MINUTE, '3 hours ago', '3h'),
+    ('days', 3 * DAY + HOUR, '3 days ago', '3d'),
+    ('months', 65 * DAY, '2 months ago', '2mo'),
+    ('years', 800 * DAY, '2 years ago', '2y'),
+    ('future-hours', -(2 * HOUR + 30 * MINUTE), 'in 2 hours', 'in 2h'),
+    ('future-day', -(DAY + HOUR), 'in 1 day', 'in 1d'),
+]
+
+with scratch() as (root, env):
+    now = time.time()
+    for name, age, _, _ in files:
+        path = os.path.join(root, name)
+        open(path, 'w').close()
+        os.utime(path, (now - age, now - age))
+
+    # The column is right aligned and as wide as the longest thing each style can show
+    for style, column, index in [('relative', 14, 2), ('compact', 7, 3)]:
+        fls_output = run(['-l', '--time-style=' + style, root], env=env).stdout.decode()
+        lines = fls_output.splitlines()[1:]
+        for entry in files:
+            name, expected = entry[0], entry[index]
+            line = [l for l in lines if l.endswith(' ' + name)]
+            if not line or not line[0].endswith(' ' + expected.rjust(column) + ' ' + name):
+                fail(style, name, 'should show', repr(expected), 'but the line is', line)
+
+finish()
@@ -0,0 +1,35 @@
+import os
+
+from common import check, compare_with_ls, finish, run, scratch
+
+with scratch() as (root, env):
+    # Several windows of directory records, and more entries than the stats kept from counting
+    # blocks. Every entry is as wide as the others in every column, so the columns of a streamed
+    # listing, which only grow, come out the same as GNU's.
+    huge = os.path.join(root, 'huge')
+    os.mkdir(huge)
+    for i in range(20000):
+        path = os.path.join(huge, 'entry-%05d-' % i + 'x' * 30)
+        if i % 1000 == 0:
+            os.mkdir(path)
+            open(os.path.join(path, 'inside'), 'w').close()
+            os.utime(os.path.join(path, 'inside'), (1000000000, 1000000000))
+        else:
+            open(path, 'w').close()
+        os.utime(path, (1000000000, 1000000000))
+    os.utime(huge, (1000000000, 1000000000))
+
+    for args in [['-U'], ['-1U'], ['-lU'], ['-lUa'], ['-lUi'], ['-RU'], ['-lRU'], ['-l'],
+                 ['-lR']]:
+        compare_with_ls([*args, 'huge'], root, env)
+
+    # Read ahead one window at a time, then streamed or read to the end by the main thread
+    for args in [['-RU'], ['-lRU'], ['-lR'], ['-R']]:
+        sequential = run([*args, 'huge'], root, env)
+        parallel = run(['--parallel=4', *args, 'huge'], root, env)
+        what = ' '.join(['--parallel=4', *args])
+        check(what + ' stdout', parallel.stdout, sequential.stdout)
+        check(what + ' stderr', parallel.stderr, sequential.stderr)
+        check(what + ' exit status', parallel.returncode, sequential.returncode)
+
+finish()
@@ -1,14 +1,8 @@
-import json
 import subprocess
 import difflib
 import os
 
-p = subprocess.run(['cargo', 'build', '--message-format=json'],
-                   stdout=subprocess.PIPE,
-                   check=True)
-
-messages = [m for m in p.stdout.splitlines() if len(m) > 0]
-exe_location = json.loads(messages[-1])['executable']
+from common import exe_location
 
 sort_orders = ['-c', '-t', '-f', '-rc', '-rt', '-rf']
 
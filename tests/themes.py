@@ -0,0 +1,120 @@
+import grp
+import os
+import pwd
+import re
+
+from common import check, finish, run, scratch
+
+ESCAPE = re.compile('\x1b\\[([0-9;]*)m')
+
+
+def styled_as(line, codes, text):
+    """Whether `text` comes right after an escape with `codes` in `line`, give or take padding"""
+    return re.search(re.escape('\x1b[%sm' % codes) + ' *' + re.escape(text), line) is not None
+
+
+# Every element gets a colour of its own, so each can be found in the output
+ELEMENTS = ['in', 'bl', 'ur', 'uw', 'ux', 'gr', 'gw', 'gx', 'tr', 'tw', 'tx', 'xx', 'lc', 'uu',
+            'un', 'gu', 'gn', 'sn', 'da', 'la']
+CODES = {element: '0;38;5;%d' % (100 + i) for i, element in enumerate(ELEMENTS)}
+EVERY_ELEMENT = ':'.join('%s=%s' % (element, CODES[element]) for element in ELEMENTS)
+
+with scratch() as (root, env):
+    env = dict(env, TERM='xterm-256color')
+    for name in ['COLORTERM', 'FLS_COLORS', 'NO_COLOR', 'LS_COLORS']:
+        env.pop(name, None)
+    path = os.path.join(root, 'other')
+    with open(path, 'w') as f:
+        f.write('hello')
+    os.chmod(path, 0o751)
+    os.chown(path, 65534, 65534)
+    path = os.path.join(root, 'mine')
+    open(path, 'w').close()
+    os.chmod(path, 0o620)
+    os.symlink('mine', os.path.join(root, 'link'))
+    other_user = pwd.getpwuid(65534).pw_name
+    other_group = grp.getgrgid(65534).gr_name
+    inode = str(os.lstat(os.path.join(root, 'other')).st_ino)
+
+    def listing(args, variables={}):
+        result = run(['-lis', '--color=always', *args], root, dict(env, **variables))
+        check(' '.join(args) + ' status', result.returncode, 0)
+        return result
+
+    lines = {}
+    for line in listing(['other', 'mine', 'link'], {'FLS_COLORS': EVERY_ELEMENT}).stdout.decode() \
+            .splitlines()[1:]:
+        lines[ESCAPE.sub('', line).split(' -> ')[0].split()[-1]] = line
+    # Each element and what the text styled as it starts with, for each file
+    expected = {
+        'other': [('in', inode), ('bl', ''), ('ur', 'r'), ('uw', 'w'), ('ux', 'x'), ('gr', 'r'),
+                  ('xx', '-'), ('gx', 'x'), ('tx', 'x'), ('lc', '1'), ('un', other_user),
+                  ('gn', other_group), ('sn', '5'), ('da', '')],
+        'mine': [('ur', 'r'), ('uw', 'w'), ('gw', 'w'), ('xx', '-'), ('uu', 'root'),
+                 ('gu', 'root'), ('sn', '0')],
+        'link': [('tr', 'r'), ('tw', 'w'), ('tx', 'x'), ('la', ' -> ')],
+    }
+    for name, elements in expected.items():
+        for element, start in elements:
+            if not styled_as(lines.get(name, ''), CODES[element], start):
+                check(name + ' ' + element, lines.get(name), CODES[element] + ' before ' + start)
+    # Dates get their own style
+    if not re.search(re.escape('\x1b[%sm' % CODES['da']) + r'\w{3} [ \d]\d ', lines['other']):
+        check('date', lines['other'], CODES['da'] + ' before a date')
+
+    # The presets, where the theme file and FLS_COLORS do not say otherwise
+    for args, element, codes in [([], 'sn', '1;32'), ([], 'da', '0;34'), ([], 'uu', '1;33'),
+                                 (['--theme=dark'], 'un', '0;33'),
+                                 (['--theme=light'], 'uu', '1;38;5;94'),
+                                 (['--theme=light'], 'un', '0;38;5;94'),
+                                 (['--theme=light'], 'sn', '1;32')]:
+        name = 'other' if element == 'un' else 'mine'
+        line = listing([*args, name]).stdout.decode().splitlines()[-1]
+        start = {'sn': '0', 'da': '', 'uu': 'root', 'un': other_user}[element]
+        if not styled_as(line, codes, start):
+            check(' '.join(args) + ' ' + element, line, codes + ' before ' + start)
+
+    # The theme file overrides the preset, FLS_COLORS the theme file, and --no-config skips only
+    # the file
+    theme = os.path.join(root, 'fls', 'theme')
+    os.mkdir(os.path.dirname(theme))
+    with open(theme, 'w') as f:
+        f.write('# sizes\nsn=35\n  # and links\nlc=1;36:in=0;91\n')
+    for args, variables, expected_codes in [
+            ([], {}, {'sn': '0;35', 'lc': '1;36', 'in': '0;91'}),
+            ([], {'FLS_COLORS': 'sn=1;33'}, {'sn': '1;33', 'lc': '1;36'}),
+            (['--no-config'], {}, {'sn': '1;32', 'lc': '0;37', 'in': '0;35'}),
+            (['--no-config'], {'FLS_COLORS': 'lc=1'}, {'lc': '1;37'}),
+            ([], {'FLS_COLORS': 'sn=0'}, {'sn': ''}),
+            ([], {'FLS_COLORS': 'sn=01;95'}, {'sn': '1;95'}),
+            ([], {'FLS_COLORS': 'sn=38;2;1;2;3'}, {'sn': '0;38;2;1;2;3'}),
+            ([], {'FLS_COLORS': 'sn=4;32;5'}, {'sn': '0;32'})]:
+        what = ' '.join([*args, *('%s=%s' % v for v in variables.items())])
+        variables = dict(variables, COLORTERM='truecolor')
+        line = listing([*args, 'other'], variables).stdout.decode().splitlines()[-1]
+        for element, codes in expected_codes.items():
+            start = {'sn': '5', 'lc': '1', 'in': inode}[element]
+            if not styled_as(line, codes, start):
+                check(what + ' ' + element, line, codes + ' before ' + start)
+
+    # Mistakes are reported with where they are, and the rest of the theme still applies
+    with open(theme, 'w') as f:
+        f.write('sn=35\nbogus=1\nda=purple:lc\n')
+    result = listing(['other'], {'FLS_COLORS': 'in=31:zz=1::la=38;5'})
+    check('mistakes stderr', result.stderr.decode(),
+          "fls: %s:2: ignoring unknown theme element 'bogus'\n"
+          "fls: %s:3: ignoring invalid style for 'da': 'purple'\n"
+          "fls: %s:3: ignoring theme entry without a value: 'lc'\n"
+          "fls: FLS_COLORS: ignoring unknown theme element 'zz'\n"
+          "fls: FLS_COLORS: ignoring invalid style for 'la': '38;5'\n" % (theme, theme, theme))
+    line = result.stdout.decode().splitlines()[-1]
+    for codes, start in [('0;35', '5'), ('0;31', inode)]:
+        if not styled_as(line, codes, start):
+            check('mistakes', line, codes + ' before ' + start)
+
+    # Without colour the theme is not even read
+    result = run(['-l', '--color=never', 'other'], root, dict(env, FLS_COLORS='zz=1'))
+    check('uncoloured stderr', result.stderr, b'')
+    check('uncoloured escapes', b'\x1b' in result.stdout, False)
+
+finish()